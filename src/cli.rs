@@ -0,0 +1,561 @@
+use crate::parser::file::{filename_to_node, format_flake_file, write_flake, write_flake_and_lock};
+use crate::parser::flake::{Dep, DepAttr, DepList, Flake, InputStyle, InputsLayout};
+use crate::parser::flake_ref::FlakeRef;
+use crate::parser::lock::{FlakeLock, LockedInput};
+use crate::parser::packages::PackageIndex;
+use crate::user::rust::generated_flake;
+
+use anyhow::anyhow;
+use rust_nix_templater::{options::RustToolchainChannel, *};
+use serde_json::json;
+use smol_str::SmolStr;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Imperative management of declarative packages.
+/// Runs the interactive interface when no subcommand is given.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "flake_generator")]
+pub(crate) struct Opts {
+    #[structopt(subcommand)]
+    pub(crate) command: Option<Command>,
+    /// Package index to pick dependencies from, instead of the cached one
+    #[structopt(long, global = true)]
+    pub(crate) packages: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum Command {
+    /// Manage the inputs of an existing flake
+    Input {
+        #[structopt(subcommand)]
+        command: InputCommand,
+    },
+    /// Manage the dependencies of the derivations built by the outputs
+    Dep {
+        #[structopt(subcommand)]
+        command: DepCommand,
+    },
+    /// Search the packages of nixpkgs, without going through the network
+    Packages {
+        #[structopt(subcommand)]
+        command: PackagesCommand,
+    },
+    /// Generate a flake for a Rust project in the current directory
+    Rust {
+        #[structopt(subcommand)]
+        command: RustCommand,
+    },
+    /// Format a flake the way nixpkgs-fmt would
+    Fmt {
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct FlakeFile {
+    /// Path to the flake to modify
+    #[structopt(short, long, default_value = "flake.nix")]
+    file: String,
+    /// Copy the flake to flake.nix.bak before modifying it
+    #[structopt(long)]
+    backup: bool,
+}
+
+/// The flake read by commands that don't modify it
+#[derive(Debug, StructOpt)]
+pub(crate) struct FlakePath {
+    /// Path to the flake to read
+    #[structopt(short, long, default_value = "flake.nix")]
+    file: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum InputCommand {
+    /// Add an input
+    Add {
+        name: String,
+        url: String,
+        /// The input is not a flake
+        #[structopt(long)]
+        no_flake: bool,
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+    /// Remove an input
+    Rm {
+        name: String,
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+    /// Rename an input, along with its uses in the outputs and the follows pointing to it
+    Rename {
+        old_name: String,
+        new_name: String,
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+    /// Write an input, or every input, as a url string or as an attribute set
+    Convert {
+        /// Input to convert, all of them if not set
+        name: Option<String>,
+        /// `url` or `attrs`
+        #[structopt(long)]
+        to: InputStyle,
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+    /// Rewrite every input in the same layout, sorted by name
+    Normalize {
+        /// `nested`, `sets` or `dotted`
+        #[structopt(long, default_value = "nested")]
+        layout: InputsLayout,
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+    /// List the inputs the outputs never use
+    Unused {
+        /// Remove them
+        #[structopt(long)]
+        remove: bool,
+        /// Copy the flake to flake.nix.bak before removing them
+        #[structopt(long, requires = "remove")]
+        backup: bool,
+        #[structopt(flatten)]
+        file: FlakePath,
+    },
+    /// List the inputs
+    Ls {
+        /// Print the inputs as JSON
+        #[structopt(long)]
+        json: bool,
+        #[structopt(flatten)]
+        file: FlakePath,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum DepCommand {
+    /// Add a package of nixpkgs to a list of dependencies, creating it if needed
+    Add {
+        /// Derivation, as listed by `dep ls`, e.g. devShell
+        derivation: String,
+        /// `buildInputs`, `nativeBuildInputs` or `packages`
+        attr: DepAttr,
+        /// Attribute name of the package in nixpkgs, e.g. openssl
+        package: String,
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+    /// Remove a dependency
+    Rm {
+        derivation: String,
+        attr: DepAttr,
+        /// The dependency as it is written, e.g. pkgs.openssl
+        name: String,
+        #[structopt(flatten)]
+        file: FlakeFile,
+    },
+    /// List the dependencies of every derivation
+    Ls {
+        #[structopt(flatten)]
+        file: FlakePath,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum PackagesCommand {
+    /// Cache the output of `nix search --json nixpkgs` or `nix-env -qaP --json`,
+    /// where `search` and the interactive interface find it
+    Import { file: String },
+    /// Search packages by attribute path or description
+    Search { query: String },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum RustCommand {
+    /// Generate the flake
+    Generate(RustOptions),
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct RustOptions {
+    /// Package name, taken from Cargo.toml if not set
+    #[structopt(long)]
+    name: Option<String>,
+    /// Rust toolchain channel: stable, beta or nightly
+    #[structopt(long, default_value = "stable")]
+    toolchain: String,
+    /// Directory to generate the flake in
+    #[structopt(long, default_value = ".")]
+    out_dir: PathBuf,
+    #[structopt(long)]
+    description: Option<String>,
+    #[structopt(long)]
+    long_description: Option<String>,
+    #[structopt(long)]
+    executable: Option<String>,
+    #[structopt(long)]
+    license: Option<String>,
+    /// Systems to build for, e.g. x86_64-linux
+    #[structopt(long)]
+    systems: Vec<String>,
+    #[structopt(long)]
+    icon: Option<String>,
+    #[structopt(long)]
+    desktop_name: Option<String>,
+    #[structopt(long)]
+    desktop_generic_name: Option<String>,
+    #[structopt(long)]
+    desktop_comment: Option<String>,
+    #[structopt(long)]
+    desktop_categories: Option<String>,
+    #[structopt(long)]
+    cachix_name: Option<String>,
+    #[structopt(long)]
+    cachix_key: Option<String>,
+    /// Don't generate build outputs
+    #[structopt(long)]
+    no_build: bool,
+    /// Don't generate app outputs
+    #[structopt(long)]
+    no_app: bool,
+    /// Package the library instead of a binary
+    #[structopt(long)]
+    lib: bool,
+    /// Generate a github actions workflow
+    #[structopt(long)]
+    github_ci: bool,
+    /// Generate a gitlab CI config
+    #[structopt(long)]
+    gitlab_ci: bool,
+}
+
+/// exit code when the requested operation failed
+const EXIT_FAILURE: i32 = 1;
+/// exit code when the flake could not be read or parsed
+const EXIT_INVALID_FLAKE: i32 = 2;
+
+/// what went wrong when running a command, decides the exit code
+pub(crate) enum Failure {
+    InvalidFlake(anyhow::Error),
+    Failed(anyhow::Error),
+}
+
+impl Failure {
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            Failure::InvalidFlake(_) => EXIT_INVALID_FLAKE,
+            Failure::Failed(_) => EXIT_FAILURE,
+        }
+    }
+
+    pub(crate) fn error(&self) -> &anyhow::Error {
+        match self {
+            Failure::InvalidFlake(err) | Failure::Failed(err) => err,
+        }
+    }
+}
+
+fn load_flake(filename: &str) -> Result<Flake, Failure> {
+    filename_to_node(filename, &SmolStr::new(filename))
+        .map(Flake::new)
+        .map_err(Failure::InvalidFlake)
+}
+
+fn save_flake(file: &FlakeFile, flake: anyhow::Result<Flake>) -> Result<(), Failure> {
+    save_flake_and_lock(file, flake, FlakeLock::clone)
+}
+
+/// saves the flake, and the lock file next to it edited by `lock_edit` if there is one
+fn save_flake_and_lock(
+    file: &FlakeFile,
+    flake: anyhow::Result<Flake>,
+    lock_edit: impl FnOnce(&FlakeLock) -> FlakeLock,
+) -> Result<(), Failure> {
+    let flake = flake.map_err(Failure::Failed)?;
+    // only the lines the command changed are formatted
+    let flake = match fs::read_to_string(&file.file) {
+        Ok(original) => flake.format_changes(&original),
+        Err(_) => flake,
+    };
+    // read before writing anything, so that a broken lock file leaves the flake untouched
+    let lock = FlakeLock::read_for(&file.file).map_err(Failure::Failed)?;
+    let edited = lock
+        .as_ref()
+        .map(lock_edit)
+        .filter(|edited| Some(edited) != lock.as_ref());
+    write_flake_and_lock(&file.file, &flake, edited.as_ref(), file.backup).map_err(Failure::Failed)
+}
+
+impl Command {
+    /// runs the command, with the package index given by `--packages` if any
+    pub(crate) fn run(self, packages: Option<&str>) -> Result<(), Failure> {
+        match self {
+            Command::Input { command } => command.run(),
+            Command::Dep { command } => command.run(packages),
+            Command::Packages { command } => command.run(packages).map_err(Failure::Failed),
+            Command::Rust {
+                command: RustCommand::Generate(options),
+            } => {
+                let options = options.into_options().map_err(Failure::Failed)?;
+                let flake = generated_flake(&options);
+                run_with_options(options, false).map_err(|err| {
+                    Failure::Failed(anyhow!(format!("rust-nix-templater failed: {}", err)))
+                })?;
+                format_flake_file(&flake).map_err(Failure::Failed)
+            }
+            Command::Fmt { file } => {
+                let flake = load_flake(&file.file)?;
+                write_flake(&file.file, &flake.format(), file.backup).map_err(Failure::Failed)
+            }
+        }
+    }
+}
+
+impl InputCommand {
+    fn run(self) -> Result<(), Failure> {
+        match self {
+            InputCommand::Add {
+                name,
+                url,
+                no_flake,
+                file,
+            } => {
+                FlakeRef::from_str(&url).map_err(|err| {
+                    Failure::Failed(anyhow!(format!("invalid url {}: {}", url, err)))
+                })?;
+                let flake = load_flake(&file.file)?;
+                save_flake(&file, flake.add_input(&name, &url, !no_flake))
+            }
+            InputCommand::Rm { name, file } => {
+                let flake = load_flake(&file.file)?;
+                save_flake_and_lock(&file, flake.remove_input(&name), |lock| {
+                    lock.remove_input(&name)
+                })
+            }
+            InputCommand::Rename {
+                old_name,
+                new_name,
+                file,
+            } => {
+                let flake = load_flake(&file.file)?;
+                save_flake_and_lock(&file, flake.rename_input(&old_name, &new_name), |lock| {
+                    lock.rename_input(&old_name, &new_name)
+                })
+            }
+            InputCommand::Convert { name, to, file } => {
+                let flake = load_flake(&file.file)?;
+                let converted = match name {
+                    Some(name) => flake.convert_input(&name, to),
+                    None => flake.convert_inputs(to),
+                };
+                save_flake(&file, converted)
+            }
+            InputCommand::Normalize { layout, file } => {
+                let flake = load_flake(&file.file)?;
+                save_flake(&file, flake.normalize_inputs(layout))
+            }
+            InputCommand::Unused {
+                remove,
+                backup,
+                file,
+            } => {
+                let flake = load_flake(&file.file)?;
+                let unused = flake.unused_inputs().map_err(Failure::Failed)?;
+                if remove {
+                    let file = FlakeFile {
+                        file: file.file,
+                        backup,
+                    };
+                    return save_flake_and_lock(&file, flake.remove_unused_inputs(), |lock| {
+                        unused
+                            .iter()
+                            .fold(lock.clone(), |lock, name| lock.remove_input(name))
+                    });
+                }
+                for name in unused {
+                    println!("{}", name);
+                }
+                Ok(())
+            }
+            InputCommand::Ls { json, file } => {
+                let flake = load_flake(&file.file)?;
+                let lock = FlakeLock::read_for(&file.file).map_err(Failure::Failed)?;
+                let statuses = lock.as_ref().map(|lock| lock.statuses(&flake));
+                let locked = |name: &str| {
+                    statuses
+                        .iter()
+                        .flatten()
+                        .find(|status| status.name == name)
+                        .and_then(|status| status.locked.clone())
+                };
+                let lock_only = statuses
+                    .iter()
+                    .flatten()
+                    .filter(|status| !status.in_flake)
+                    .collect::<Vec<_>>();
+                let inputs = flake.inputs();
+                if json {
+                    let inputs = inputs
+                        .iter()
+                        .map(|input| {
+                            let follows = input
+                                .follows()
+                                .into_iter()
+                                .map(|follows| (follows.nested, json!(follows.target)))
+                                .collect::<serde_json::Map<_, _>>();
+                            json!({
+                                "name": input.name(),
+                                "url": input.url(),
+                                "flake": input.is_flake(),
+                                "follows": follows,
+                                "locked": locked(input.name()).map(locked_to_json),
+                                "in_flake": true,
+                            })
+                        })
+                        .chain(lock_only.iter().map(|status| {
+                            json!({
+                                "name": status.name,
+                                "locked": status.locked.clone().map(locked_to_json),
+                                "in_flake": false,
+                            })
+                        }))
+                        .collect::<Vec<_>>();
+                    println!("{}", serde_json::Value::Array(inputs));
+                } else {
+                    for input in inputs {
+                        let mut line =
+                            format!("{} {}", input.name(), input.url().unwrap_or_default());
+                        if !input.is_flake() {
+                            line.push_str(" (not a flake)");
+                        }
+                        if lock.is_some() {
+                            match locked(input.name()) {
+                                Some(locked) => line.push_str(&format!("  {}", locked)),
+                                None => line.push_str("  [not locked]"),
+                            }
+                        }
+                        println!("{}", line);
+                    }
+                    for status in lock_only {
+                        println!("{}", status);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl DepCommand {
+    fn run(self, packages: Option<&str>) -> Result<(), Failure> {
+        match self {
+            DepCommand::Add {
+                derivation,
+                attr,
+                package,
+                file,
+            } => {
+                let flake = load_flake(&file.file)?;
+                // the index may be out of date, so this is only a warning
+                if let Ok(Some(index)) = PackageIndex::load(packages) {
+                    if index.package(&package).is_none() {
+                        eprintln!("warning: {} is not in the package index", package);
+                    }
+                }
+                save_flake(
+                    &file,
+                    flake.add_dep(&DepList { derivation, attr }, &package),
+                )
+            }
+            DepCommand::Rm {
+                derivation,
+                attr,
+                name,
+                file,
+            } => {
+                let flake = load_flake(&file.file)?;
+                save_flake(
+                    &file,
+                    flake.remove_dep(&Dep {
+                        derivation,
+                        attr,
+                        name,
+                    }),
+                )
+            }
+            DepCommand::Ls { file } => {
+                for dep in load_flake(&file.file)?.deps() {
+                    println!("{}", dep);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl PackagesCommand {
+    fn run(self, packages: Option<&str>) -> anyhow::Result<()> {
+        match self {
+            PackagesCommand::Import { file } => {
+                let cache = PackageIndex::import(&file)?;
+                println!("cached the package index in {}", cache.display());
+            }
+            PackagesCommand::Search { query } => {
+                let index = PackageIndex::load(packages)?.ok_or_else(|| {
+                    anyhow!("no package index, import one with `flake_generator packages import`")
+                })?;
+                for package in index.search(&query) {
+                    println!("{}", package);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn locked_to_json(locked: LockedInput) -> serde_json::Value {
+    json!({
+        "node": locked.node,
+        "rev": locked.rev,
+        "narHash": locked.nar_hash,
+        "lastModified": locked.last_modified,
+        "owner": locked.owner,
+        "repo": locked.repo,
+        "follows": locked.follows,
+    })
+}
+
+impl RustOptions {
+    fn into_options(self) -> anyhow::Result<Options> {
+        let rust_toolchain_channel = RustToolchainChannel::from_str(&self.toolchain)
+            .map_err(|_| anyhow!("{} is not a valid toolchain channel.", self.toolchain))?;
+        let mut options = Options::default();
+        options.out_dir = self.out_dir;
+        options.package_name = self.name;
+        options.rust_toolchain_channel = rust_toolchain_channel;
+        options.package_description = self.description;
+        options.package_long_description = self.long_description;
+        options.package_executable = self.executable;
+        options.package_license = self.license;
+        let systems = self.systems;
+        options.package_systems = (!systems.is_empty()).then(|| systems);
+        options.package_icon = self.icon;
+        options.package_xdg_desktop_name = self.desktop_name;
+        options.package_xdg_generic_name = self.desktop_generic_name;
+        options.package_xdg_comment = self.desktop_comment;
+        options.package_xdg_categories = self.desktop_categories;
+        options.cachix_name = self.cachix_name;
+        options.cachix_public_key = self.cachix_key;
+        options.disable_build = self.no_build;
+        options.disable_app = self.no_app;
+        options.package_lib = self.lib;
+        options.github_ci = self.github_ci;
+        options.gitlab_ci = self.gitlab_ci;
+        Ok(options)
+    }
+}
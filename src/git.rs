@@ -0,0 +1,137 @@
+use crate::parser::flake::Pin;
+
+use anyhow::{anyhow, bail};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+/// how many tags and commits are offered when pinning an input
+const MAX_TAGS: usize = 20;
+const MAX_COMMITS: usize = 50;
+
+/// A tag or commit of a local git checkout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Revision {
+    pub(crate) rev: String,
+    pub(crate) tag: Option<String>,
+    /// as `YYYY-MM-DD`
+    pub(crate) date: String,
+    pub(crate) subject: String,
+}
+
+impl Revision {
+    /// what the revision is shown as, its tag or its abbreviated hash
+    pub(crate) fn label(&self) -> &str {
+        match &self.tag {
+            Some(tag) => tag,
+            None => &self.rev[..self.rev.len().min(7)],
+        }
+    }
+
+    pub(crate) fn pin(&self) -> Pin {
+        match &self.tag {
+            Some(tag) => Pin::Tag {
+                name: tag.clone(),
+                rev: self.rev.clone(),
+            },
+            None => Pin::Rev(self.rev.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  ", self.label())?;
+        if self.tag.is_some() {
+            write!(f, "(tag) ")?;
+        }
+        write!(f, "{} {}", self.date, self.subject)
+    }
+}
+
+/// the local checkout an input is fetched from, for `git+file:` and `path:` urls
+/// relative paths are relative to the directory of the flake at `flake_file`, as for nix
+pub(crate) fn local_checkout(url: &str, flake_file: &str) -> Option<String> {
+    let path = url.split('?').next().unwrap_or_default();
+    let path = path
+        .strip_prefix("git+file:")
+        .or_else(|| path.strip_prefix("path:"))
+        .or_else(|| Some(path).filter(|path| path.starts_with('/') || path.starts_with('.')))?;
+    // `git+file:///foo` and `git+file:/foo` are the same
+    let path = Path::new(path.strip_prefix("//").unwrap_or(path));
+    let path = match Path::new(flake_file).parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    };
+    Some(path.to_string_lossy().to_string())
+}
+
+/// runs git in `checkout`, returning what it printed
+fn git(checkout: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(checkout)
+        .args(args)
+        .output()
+        .map_err(|err| anyhow!(format!("could not run git: {}", err)))?;
+    if !output.status.success() {
+        bail!(
+            "git failed in {}: {}",
+            checkout,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// parses lines of tab separated revision, date and subject
+fn parse_revisions(output: &str, tagged: bool) -> Vec<Revision> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // subjects may contain tabs too
+            let mut fields = line.splitn(if tagged { 4 } else { 3 }, '\t');
+            let tag = if tagged {
+                Some(fields.next()?.to_string())
+            } else {
+                None
+            };
+            Some(Revision {
+                tag,
+                rev: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                subject: fields.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// the most recent tags, then the most recent commits of the checked out branch
+pub(crate) fn recent_revisions(checkout: &str) -> anyhow::Result<Vec<Revision>> {
+    // annotated tags point to a tag object, `*objectname` is the commit behind it
+    let tags = git(
+        checkout,
+        &[
+            "for-each-ref",
+            "--sort=-creatordate",
+            &format!("--count={}", MAX_TAGS),
+            "--format=%(refname:short)%09%(if)%(*objectname)%(then)%(*objectname)%(else)%(objectname)%(end)%09%(creatordate:short)%09%(contents:subject)",
+            "refs/tags",
+        ],
+    )?;
+    let commits = git(
+        checkout,
+        &[
+            "log",
+            &format!("--max-count={}", MAX_COMMITS),
+            "--date=short",
+            "--format=%H%x09%cd%x09%s",
+        ],
+    )?;
+    let mut revisions = parse_revisions(&tags, true);
+    revisions.extend(parse_revisions(&commits, false));
+    if revisions.is_empty() {
+        bail!("{} has no commits", checkout);
+    }
+    Ok(revisions)
+}
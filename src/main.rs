@@ -1,9 +1,16 @@
+mod cli;
+mod git;
 mod parser;
 mod user;
 
 use anyhow::anyhow;
+use cli::Opts;
 use parser::file::{filename_to_node, write_to_node};
-use parser::utils::remove_input;
+use parser::flake::{Dep, DepList, Flake, Follows};
+use parser::flake_ref::FlakeRef;
+use parser::lock::FlakeLock;
+use std::str::FromStr;
+use structopt::StructOpt;
 use user::*;
 
 struct ActionStack {
@@ -34,15 +41,204 @@ impl ActionStack {
     }
 }
 
+/// stages the result of an edit of the flake and goes back to the main menu,
+/// or reports the error
+/// nothing is written until the changes are reviewed
+fn apply_edit(
+    user_data: &mut UserMetadata,
+    action_stack: &mut ActionStack,
+    result: anyhow::Result<Flake>,
+    context: &str,
+) {
+    apply_lock_edit(user_data, action_stack, result, context, FlakeLock::clone)
+}
+
+/// same as `apply_edit`, also applying `lock_edit` to the lock file if there is one
+fn apply_lock_edit(
+    user_data: &mut UserMetadata,
+    action_stack: &mut ActionStack,
+    result: anyhow::Result<Flake>,
+    context: &str,
+    lock_edit: impl FnOnce(&FlakeLock) -> FlakeLock,
+) {
+    match result {
+        Ok(flake) => {
+            let lock = user_data.lock.as_ref().map(lock_edit);
+            user_data.new_edit(flake, lock);
+            action_stack.push(UserAction::IntroParsed);
+        }
+        Err(err) => action_stack.push(UserAction::Error(anyhow!(format!("{}: {}", context, err)))),
+    }
+}
+
+/// applies several items selected at once, as a single edit that is undone at once
+fn apply_batch(
+    user_data: &mut UserMetadata,
+    action_stack: &mut ActionStack,
+    selections: Vec<UserPrompt>,
+) {
+    if let UserAction::Rust(_) = action_stack.current() {
+        let toggles = selections
+            .iter()
+            .filter_map(|selection| match selection {
+                UserPrompt::Rust(prompt) if prompt.is_toggle() => Some(prompt),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if toggles.len() == selections.len() {
+            for toggle in toggles {
+                toggle.process_prompt(action_stack, user_data);
+            }
+        } else {
+            action_stack.push(UserAction::Error(anyhow!(
+                "only options that are toggled can be selected together"
+            )));
+        }
+        return;
+    }
+    let items = selections
+        .iter()
+        .filter_map(|selection| match selection {
+            UserPrompt::Other(item) => Some(item.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if items.len() != selections.len() {
+        action_stack.push(UserAction::Error(anyhow!(
+            "only items of the list can be selected together"
+        )));
+        return;
+    }
+    let flake = user_data.flake().clone();
+    match action_stack.current() {
+        UserAction::RemoveInput | UserAction::UnusedInputs(_) => {
+            let names = items
+                .iter()
+                .map(|item| input_name(item))
+                .collect::<Vec<_>>();
+            let result = names
+                .iter()
+                .try_fold(flake, |flake, name| flake.remove_input(name));
+            apply_lock_edit(
+                user_data,
+                action_stack,
+                result,
+                "could not remove inputs",
+                |lock| {
+                    names
+                        .iter()
+                        .fold(lock.clone(), |lock, name| lock.remove_input(name))
+                },
+            );
+        }
+        UserAction::AddDep => {
+            let list = match user_data.selected_dep_list.clone() {
+                Some(list) => list,
+                None => {
+                    action_stack.push(UserAction::Error(anyhow!(
+                        "no list of dependencies selected"
+                    )));
+                    return;
+                }
+            };
+            // items start with the package's attribute path
+            let result = items
+                .iter()
+                .filter_map(|item| item.split_whitespace().next())
+                .try_fold(flake, |flake, package| flake.add_dep(&list, package));
+            apply_edit(
+                user_data,
+                action_stack,
+                result,
+                "could not add dependencies",
+            );
+        }
+        UserAction::RemoveDep => {
+            let result = items.iter().try_fold(flake, |flake, item| {
+                let dep = Dep::from_str(item)
+                    .map_err(|_| anyhow!(format!("{} is not a dependency", item)))?;
+                flake.remove_dep(&dep)
+            });
+            apply_edit(
+                user_data,
+                action_stack,
+                result,
+                "could not remove dependencies",
+            );
+        }
+        action => action_stack.push(UserAction::Error(anyhow!(format!(
+            "several items can't be selected for: {}",
+            action
+        )))),
+    }
+}
+
+fn add_pending_input(user_data: &mut UserMetadata, action_stack: &mut ActionStack, is_flake: bool) {
+    let (name, url) = match user_data.pending_input.take() {
+        Some(input) => input,
+        None => {
+            action_stack.push(UserAction::Error(anyhow!("no input to add")));
+            return;
+        }
+    };
+    let result = user_data.flake().add_input(&name, &url, is_flake);
+    apply_edit(user_data, action_stack, result, "could not add input");
+}
+
+/// lists the revisions of `checkout` the selected input can be pinned to
+fn select_revision(action_stack: &mut ActionStack, checkout: &str) {
+    match git::recent_revisions(checkout) {
+        Ok(revisions) => action_stack.push(UserAction::SelectRevision(revisions)),
+        Err(err) => action_stack.push(UserAction::Error(anyhow!(format!(
+            "could not list revisions: {}",
+            err
+        )))),
+    }
+}
+
+/// checks a url typed by the user, reporting what's wrong with it
+fn validate_url(action_stack: &mut ActionStack, url: &str) -> bool {
+    match FlakeRef::from_str(url) {
+        Ok(_) => true,
+        Err(err) => {
+            action_stack.push(UserAction::Error(anyhow!(format!(
+                "invalid url {}: {}",
+                url, err
+            ))));
+            false
+        }
+    }
+}
+
 fn main() {
-    let mut user_data = UserMetadata::default();
+    let opts = Opts::from_args();
+    match opts.command {
+        Some(command) => {
+            if let Err(failure) = command.run(opts.packages.as_deref()) {
+                eprintln!("error: {:#}", failure.error());
+                std::process::exit(failure.exit_code());
+            }
+        }
+        None => interactive(opts.packages),
+    }
+}
+
+fn interactive(packages_file: Option<String>) {
+    run_interactive(
+        &mut SkimPrompter,
+        UserMetadata::with_packages_file(packages_file),
+    );
+}
+
+/// the interactive interface, asking `prompter` what to do until the user exits
+/// returns what the user was working on
+fn run_interactive(prompter: &mut dyn Prompter, mut user_data: UserMetadata) -> UserMetadata {
     let mut action_stack = ActionStack::new();
 
     loop {
-        let cur_action = action_stack.current();
-
-        let user_selection = match user_data.get_user_prompt(cur_action) {
-            Ok(prompt) => prompt,
+        let prompts = user_data.get_user_prompts(prompter, action_stack.current());
+        let mut user_selections = match prompts {
+            Ok(prompts) => prompts,
             Err(err) => {
                 action_stack.push(UserAction::Error(anyhow!(format!(
                     "could not process prompt: {}",
@@ -51,62 +247,444 @@ fn main() {
                 continue;
             }
         };
+        if user_selections.len() > 1 {
+            apply_batch(&mut user_data, &mut action_stack, user_selections);
+            continue;
+        }
+        let user_selection = match user_selections.pop() {
+            Some(prompt) => prompt,
+            None => continue,
+        };
+        let cur_action = action_stack.current();
+        // answers to whether an input is a flake, anywhere else it's something typed
+        let user_selection = match user_selection {
+            UserPrompt::Yes | UserPrompt::No if !matches!(cur_action, UserAction::IsInputFlake) => {
+                UserPrompt::Other(user_selection.to_string().into())
+            }
+            user_selection => user_selection,
+        };
 
         match user_selection {
             UserPrompt::Back => {
                 action_stack.pop();
             }
+            UserPrompt::Exit | UserPrompt::StartOver if user_data.has_pending_changes() => {
+                action_stack.push(user_data.review_changes());
+            }
             UserPrompt::Exit => break,
             UserPrompt::StartOver => {
-                user_data = UserMetadata::default();
+                user_data.start_over();
                 action_stack.clear();
             }
+            UserPrompt::ReviewChanges => action_stack.push(user_data.review_changes()),
+            UserPrompt::UndoEdit | UserPrompt::RedoEdit => {
+                if user_selection == UserPrompt::UndoEdit {
+                    user_data.undo();
+                } else {
+                    user_data.redo();
+                }
+                // the diff being reviewed is stale now
+                if let UserAction::ReviewChanges(_) = cur_action {
+                    action_stack.pop();
+                    action_stack.push(user_data.review_changes());
+                }
+            }
+            UserPrompt::WriteChanges | UserPrompt::WriteChangesWithBackup => {
+                let backup = user_selection == UserPrompt::WriteChangesWithBackup;
+                match write_to_node(&user_data, backup) {
+                    Ok(()) => {
+                        // what was written has its edited lines formatted
+                        if let Some(written) = user_data.flake_to_write() {
+                            user_data.original = Some(written.to_string());
+                            user_data.new_flake(written);
+                        }
+                        user_data.original_lock = user_data.lock.clone();
+                        action_stack.pop();
+                    }
+                    Err(err) => action_stack.push(UserAction::Error(err)),
+                }
+            }
+            UserPrompt::DiscardChanges => {
+                let original = user_data.original.clone().unwrap_or_default();
+                match Flake::parse(original) {
+                    Ok(flake) => {
+                        user_data.new_flake(flake);
+                        action_stack.pop();
+                    }
+                    Err(err) => action_stack.push(UserAction::Error(err)),
+                }
+            }
             UserPrompt::Create => action_stack.push(UserAction::CreateNew),
             UserPrompt::Modify => action_stack.push(UserAction::ModifyExisting),
             UserPrompt::DeleteInput => action_stack.push(UserAction::RemoveInput),
             UserPrompt::AddInput => action_stack.push(UserAction::AddInput),
+            UserPrompt::ChangeInput => action_stack.push(UserAction::ChangeInput),
+            UserPrompt::RenameInput => action_stack.push(UserAction::RenameInput),
+            UserPrompt::PinInput => action_stack.push(UserAction::PinInput),
+            UserPrompt::ConvertInput => action_stack.push(UserAction::ConvertInput),
+            UserPrompt::AllInputs => {
+                user_data.selected_input = None;
+                action_stack.push(UserAction::SelectInputStyle);
+            }
+            UserPrompt::InputStyle(style) => {
+                let result = match &user_data.selected_input {
+                    Some(input) => user_data.flake().convert_input(input, style),
+                    None => user_data.flake().convert_inputs(style),
+                };
+                apply_edit(
+                    &mut user_data,
+                    &mut action_stack,
+                    result,
+                    "could not convert input",
+                );
+            }
+            UserPrompt::NormalizeInputs => action_stack.push(UserAction::SelectInputsLayout),
+            UserPrompt::FormatFlake => {
+                let result = Ok(user_data.flake().format());
+                apply_edit(
+                    &mut user_data,
+                    &mut action_stack,
+                    result,
+                    "could not format",
+                );
+            }
+            UserPrompt::InputsLayout(layout) => {
+                let result = user_data.flake().normalize_inputs(layout);
+                apply_edit(
+                    &mut user_data,
+                    &mut action_stack,
+                    result,
+                    "could not normalize inputs",
+                );
+            }
+            UserPrompt::UnusedInputs => match user_data.flake().unused_inputs() {
+                Ok(names) => action_stack.push(UserAction::UnusedInputs(names)),
+                Err(err) => action_stack.push(UserAction::Error(anyhow!(format!(
+                    "could not find unused inputs: {}",
+                    err
+                )))),
+            },
+            UserPrompt::RemoveUnused => {
+                let unused = user_data.flake().unused_inputs().unwrap_or_default();
+                let result = user_data.flake().remove_unused_inputs();
+                apply_lock_edit(
+                    &mut user_data,
+                    &mut action_stack,
+                    result,
+                    "could not remove unused inputs",
+                    |lock| {
+                        unused
+                            .iter()
+                            .fold(lock.clone(), |lock, name| lock.remove_input(name))
+                    },
+                );
+            }
+            UserPrompt::ManageFollows => action_stack.push(UserAction::ManageFollows),
+            UserPrompt::AddFollows => action_stack.push(UserAction::AddFollows),
+            UserPrompt::RetargetFollows => action_stack.push(UserAction::SetFollowsTarget),
+            UserPrompt::RemoveFollows => {
+                let follows = match user_data.selected_follows.clone() {
+                    Some(follows) => follows,
+                    None => {
+                        action_stack.push(UserAction::Error(anyhow!("no follows selected")));
+                        continue;
+                    }
+                };
+                let result = user_data.flake().remove_follows(&follows);
+                apply_edit(
+                    &mut user_data,
+                    &mut action_stack,
+                    result,
+                    "could not remove follows",
+                );
+            }
+            UserPrompt::FollowNixpkgs => {
+                let result = user_data
+                    .flake()
+                    .follow_everywhere("nixpkgs", user_data.lock.as_ref());
+                apply_edit(
+                    &mut user_data,
+                    &mut action_stack,
+                    result,
+                    "could not make inputs follow nixpkgs",
+                );
+            }
+            UserPrompt::AddDep => action_stack.push(UserAction::SelectDepList),
+            UserPrompt::RemoveDep => action_stack.push(UserAction::RemoveDep),
+            UserPrompt::Yes => add_pending_input(&mut user_data, &mut action_stack, true),
+            UserPrompt::No => add_pending_input(&mut user_data, &mut action_stack, false),
             UserPrompt::SelectLang(lang) => match lang {
                 Lang::Rust => action_stack.push(UserAction::Rust(user::rust::Action::Intro)),
-                lang => todo!("lang {}", lang),
+                lang => action_stack.push(UserAction::Error(anyhow!(format!(
+                    "there is no {} flake generator yet",
+                    lang
+                )))),
             },
             UserPrompt::Rust(prompt) => {
                 prompt.process_prompt(&mut action_stack, &mut user_data);
             }
-            UserPrompt::Other(other) => {
-                match cur_action {
-                    UserAction::Rust(action) => {
-                        action
-                            .clone()
-                            .process_action(other, &mut action_stack, &mut user_data)
-                    }
-                    UserAction::ModifyExisting => {
-                        let filename = other.as_str();
-                        match filename_to_node(filename, &other) {
-                            Err(err_msg) => action_stack.push(UserAction::Error(err_msg)),
-                            Ok(root) => {
-                                user_data.filename = Some(filename.to_string());
-                                user_data.root = Some(root);
-                                action_stack.push(UserAction::IntroParsed);
+            UserPrompt::Other(other) => match cur_action {
+                UserAction::Rust(action) => {
+                    action
+                        .clone()
+                        .process_action(other, &mut action_stack, &mut user_data)
+                }
+                UserAction::ModifyExisting => {
+                    let filename = other.as_str();
+                    match filename_to_node(filename, &other) {
+                        Err(err_msg) => action_stack.push(UserAction::Error(err_msg)),
+                        Ok(root) => {
+                            user_data.filename = Some(filename.to_string());
+                            user_data.original = Some(root.to_string());
+                            user_data.load_flake(Flake::new(root));
+                            action_stack.push(UserAction::IntroParsed);
+                            match FlakeLock::read_for(filename) {
+                                Ok(lock) => {
+                                    user_data.original_lock = lock.clone();
+                                    user_data.lock = lock;
+                                }
+                                Err(err) => action_stack.push(UserAction::Error(err)),
                             }
                         }
                     }
-                    UserAction::RemoveInput => {
-                        let inputs = user_data.inputs.as_ref().unwrap();
-                        let new_root = remove_input(
-                            user_data.root.as_ref().unwrap(),
-                            other.as_str(),
-                            Some(inputs),
-                        )
-                        .unwrap();
-                        user_data.new_root(new_root);
-                        write_to_node(&user_data);
-
-                        // TODO add in a "write to file" option at the end instead of writing after every modification
-                        action_stack.push(UserAction::IntroParsed);
+                }
+                UserAction::RemoveInput | UserAction::UnusedInputs(_) => {
+                    let name = input_name(&other);
+                    let result = user_data.flake().remove_input(name);
+                    apply_lock_edit(
+                        &mut user_data,
+                        &mut action_stack,
+                        result,
+                        "could not remove input",
+                        |lock| lock.remove_input(name),
+                    );
+                }
+                UserAction::ChangeInput => {
+                    user_data.selected_input = Some(input_name(&other).to_string());
+                    action_stack.push(UserAction::SetInputUrl);
+                }
+                UserAction::SetInputUrl => {
+                    if !validate_url(&mut action_stack, other.trim()) {
+                        continue;
                     }
-                    _ => unimplemented!(),
+                    let input = match user_data.selected_input.clone() {
+                        Some(input) => input,
+                        None => {
+                            action_stack.push(UserAction::Error(anyhow!("no input selected")));
+                            continue;
+                        }
+                    };
+                    let result = user_data.flake().set_input_url(&input, other.trim());
+                    apply_edit(
+                        &mut user_data,
+                        &mut action_stack,
+                        result,
+                        "could not change input",
+                    );
                 }
-            }
+                UserAction::RenameInput => {
+                    user_data.selected_input = Some(input_name(&other).to_string());
+                    action_stack.push(UserAction::SetInputName);
+                }
+                UserAction::SetInputName => {
+                    let input = match user_data.selected_input.clone() {
+                        Some(input) => input,
+                        None => {
+                            action_stack.push(UserAction::Error(anyhow!("no input selected")));
+                            continue;
+                        }
+                    };
+                    let new_name = other.trim();
+                    let result = user_data.flake().rename_input(&input, new_name);
+                    apply_lock_edit(
+                        &mut user_data,
+                        &mut action_stack,
+                        result,
+                        "could not rename input",
+                        |lock| lock.rename_input(&input, new_name),
+                    );
+                }
+                UserAction::PinInput => {
+                    let name = input_name(&other).to_string();
+                    let flake_file = user_data.filename.clone().unwrap_or_default();
+                    let checkout = user_data
+                        .flake()
+                        .input(&name)
+                        .and_then(|input| input.url())
+                        .and_then(|url| git::local_checkout(&url, &flake_file));
+                    user_data.selected_input = Some(name);
+                    match checkout {
+                        Some(checkout) => select_revision(&mut action_stack, &checkout),
+                        None => action_stack.push(UserAction::SetCheckout),
+                    }
+                }
+                UserAction::ConvertInput => {
+                    user_data.selected_input = Some(input_name(&other).to_string());
+                    action_stack.push(UserAction::SelectInputStyle);
+                }
+                UserAction::SetCheckout => select_revision(&mut action_stack, other.trim()),
+                UserAction::SelectRevision(revisions) => {
+                    let input = match user_data.selected_input.clone() {
+                        Some(input) => input,
+                        None => {
+                            action_stack.push(UserAction::Error(anyhow!("no input selected")));
+                            continue;
+                        }
+                    };
+                    match revisions
+                        .iter()
+                        .find(|revision| revision.label() == input_name(&other))
+                    {
+                        Some(revision) => {
+                            let result = user_data.flake().pin_input(
+                                &input,
+                                &revision.pin(),
+                                &user_data.flake_dir(),
+                            );
+                            apply_edit(
+                                &mut user_data,
+                                &mut action_stack,
+                                result,
+                                "could not pin input",
+                            );
+                        }
+                        None => action_stack.push(UserAction::Error(anyhow!(format!(
+                            "{} is not a revision",
+                            other
+                        )))),
+                    }
+                }
+                UserAction::ManageFollows => match Follows::from_str(&other) {
+                    Ok(follows) => {
+                        user_data.selected_follows = Some(follows);
+                        action_stack.push(UserAction::ModifyFollows);
+                    }
+                    Err(_) => action_stack.push(UserAction::Error(anyhow!(format!(
+                        "{} is not a follows",
+                        other
+                    )))),
+                },
+                UserAction::SetFollowsTarget => {
+                    let follows = match user_data.selected_follows.clone() {
+                        Some(follows) => follows,
+                        None => {
+                            action_stack.push(UserAction::Error(anyhow!("no follows selected")));
+                            continue;
+                        }
+                    };
+                    let result = user_data.flake().retarget_follows(&follows, other.trim());
+                    apply_edit(
+                        &mut user_data,
+                        &mut action_stack,
+                        result,
+                        "could not change follows",
+                    );
+                }
+                UserAction::AddFollows => {
+                    user_data.selected_input = Some(input_name(&other).to_string());
+                    action_stack.push(UserAction::NewFollows);
+                }
+                UserAction::NewFollows => {
+                    let input = match user_data.selected_input.clone() {
+                        Some(input) => input,
+                        None => {
+                            action_stack.push(UserAction::Error(anyhow!("no input selected")));
+                            continue;
+                        }
+                    };
+                    let mut args = other.split_whitespace();
+                    match (args.next(), args.next(), args.next()) {
+                        (Some(nested), Some(target), None) => {
+                            let result = user_data.flake().add_follows(&Follows {
+                                input,
+                                nested: nested.to_string(),
+                                target: target.to_string(),
+                            });
+                            apply_edit(
+                                &mut user_data,
+                                &mut action_stack,
+                                result,
+                                "could not add follows",
+                            );
+                        }
+                        _ => action_stack.push(UserAction::Error(anyhow!(format!(
+                            "expected an input name and the input it follows, got: {}",
+                            other
+                        )))),
+                    }
+                }
+                UserAction::SelectDepList => match DepList::from_str(&other) {
+                    Ok(list) => {
+                        user_data.selected_dep_list = Some(list);
+                        action_stack.push(UserAction::AddDep);
+                        if let Err(err) = user_data.load_packages() {
+                            action_stack.push(UserAction::Error(anyhow!(format!(
+                                "could not load the package index: {}",
+                                err
+                            ))));
+                        }
+                    }
+                    Err(_) => action_stack.push(UserAction::Error(anyhow!(format!(
+                        "{} is not a list of dependencies",
+                        other
+                    )))),
+                },
+                UserAction::AddDep => {
+                    let list = match user_data.selected_dep_list.clone() {
+                        Some(list) => list,
+                        None => {
+                            action_stack.push(UserAction::Error(anyhow!(
+                                "no list of dependencies selected"
+                            )));
+                            continue;
+                        }
+                    };
+                    // items start with the package's attribute path
+                    let package = other.split_whitespace().next().unwrap_or_default();
+                    let result = user_data.flake().add_dep(&list, package);
+                    apply_edit(
+                        &mut user_data,
+                        &mut action_stack,
+                        result,
+                        "could not add dependency",
+                    );
+                }
+                UserAction::RemoveDep => match Dep::from_str(&other) {
+                    Ok(dep) => {
+                        let result = user_data.flake().remove_dep(&dep);
+                        apply_edit(
+                            &mut user_data,
+                            &mut action_stack,
+                            result,
+                            "could not remove dependency",
+                        );
+                    }
+                    Err(_) => action_stack.push(UserAction::Error(anyhow!(format!(
+                        "{} is not a dependency",
+                        other
+                    )))),
+                },
+                UserAction::AddInput => {
+                    let mut args = other.split_whitespace();
+                    match (args.next(), args.next(), args.next()) {
+                        (Some(name), Some(url), None) => {
+                            if validate_url(&mut action_stack, url) {
+                                user_data.pending_input = Some((name.to_string(), url.to_string()));
+                                action_stack.push(UserAction::IsInputFlake);
+                            }
+                        }
+                        _ => action_stack.push(UserAction::Error(anyhow!(format!(
+                            "expected an input name and url, got: {}",
+                            other
+                        )))),
+                    }
+                }
+                _ => action_stack.push(UserAction::Error(anyhow!(format!(
+                    "{} can't be selected here",
+                    other
+                )))),
+            },
         }
     }
+    user_data
 }
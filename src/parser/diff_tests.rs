@@ -0,0 +1,27 @@
+use crate::parser::diff::unified_diff;
+
+#[test]
+pub fn diff_unchanged() {
+    let content = include_str!("../../test_data/inputs.nix");
+    assert_eq!(unified_diff(content, content, "flake.nix"), "");
+}
+
+#[test]
+pub fn diff_single_change() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+    let new = "a\nb\nc\nd\nE\nf\ng\nh\ni\nj\n";
+    assert_eq!(
+        unified_diff(old, new, "flake.nix"),
+        "--- a/flake.nix\n+++ b/flake.nix\n@@ -2,7 +2,7 @@\n b\n c\n d\n-e\n+E\n f\n g\n h\n"
+    );
+}
+
+#[test]
+pub fn diff_separate_hunks() {
+    let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+    let new = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n";
+    assert_eq!(
+        unified_diff(old, new, "flake.nix"),
+        "--- a/flake.nix\n+++ b/flake.nix\n@@ -1,3 +1,4 @@\n+0\n 1\n 2\n 3\n@@ -9,4 +10,3 @@\n 9\n 10\n 11\n-12\n"
+    );
+}
@@ -0,0 +1,62 @@
+use crate::parser::utils::NixNode;
+use parse_display::Display;
+
+/// where a node sits in the flake, with the text it spans
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display("`{text}` at line {line}, column {column}")]
+pub struct Span {
+    /// byte offsets in the flake
+    pub start: usize,
+    pub end: usize,
+    /// 1-indexed
+    pub line: usize,
+    pub column: usize,
+    /// first line of the node's text
+    pub text: String,
+}
+
+impl Span {
+    pub fn of(node: &NixNode) -> Self {
+        let root = node.ancestors().last().unwrap_or_else(|| node.clone());
+        let source = root.to_string();
+        let range = node.text_range();
+        let (start, end) = (usize::from(range.start()), usize::from(range.end()));
+        let before = &source[..start];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Self {
+            start,
+            end,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            text: source[start..end].lines().next().unwrap_or("").to_string(),
+        }
+    }
+}
+
+/// ways an edit of the flake can fail
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum AstError {
+    #[display("input {0} not found")]
+    InputNotFound(String),
+    #[display("follows {0} not found")]
+    FollowsNotFound(String),
+    #[display("derivation {0} not found")]
+    DerivationNotFound(String),
+    #[display("dependency {0} not found")]
+    DependencyNotFound(String),
+    #[display("the flake has no outputs")]
+    MissingOutputs,
+    #[display("outputs is not a function: {0}")]
+    OutputsNotLambda(Span),
+    #[display("input {0} is still used by the outputs: {1}")]
+    InputStillUsed(String, Span),
+    #[display("argument {0} found twice: {1}")]
+    DuplicateArgument(String, Span),
+    #[display("unsupported {0}: {1}")]
+    UnsupportedSyntax(String, Span),
+    /// the tree doesn't look like it should, this is a bug
+    #[display("inconsistent syntax tree, please file a bug report: {0}")]
+    InconsistentTree(String),
+}
+
+impl std::error::Error for AstError {}
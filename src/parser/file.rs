@@ -1,9 +1,12 @@
+use crate::parser::flake::Flake;
+use crate::parser::lock::{lock_filename, FlakeLock};
 use crate::parser::utils::{string_to_node, NixNode};
 use crate::user::UserMetadata;
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use smol_str::SmolStr;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 // TODO shouldn't we be concatenating the filename to the absolute path?
 pub fn filename_to_node(filename: &str, full_path: &SmolStr) -> anyhow::Result<NixNode> {
@@ -27,12 +30,139 @@ pub fn filename_to_node(filename: &str, full_path: &SmolStr) -> anyhow::Result<N
     string_to_node(content)
 }
 
-pub(crate) fn write_to_node(user_data: &UserMetadata) {
-    let stringified = user_data.root.as_ref().unwrap().to_string();
+/// path of the backup kept by `write_file`
+pub(crate) fn backup_filename(filename: &str) -> String {
+    format!("{}.bak", filename)
+}
+
+/// A file written next to the one it replaces, waiting to be renamed over it
+struct PendingFile {
+    filename: String,
+    path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl PendingFile {
+    /// writes `content` to a temporary file next to `filename`,
+    /// with the permissions of the existing file, which is first copied to
+    /// `filename.bak` if `backup` is set
+    fn write(filename: &str, content: &str, backup: bool) -> anyhow::Result<Self> {
+        // write through symlinks instead of replacing them
+        let path = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+        let existing = fs::metadata(&path).ok();
+
+        if backup && existing.is_some() {
+            fs::copy(&path, backup_filename(&path.to_string_lossy()))
+                .map_err(|err| anyhow!(format!("could not back up {}: {}", filename, err)))?;
+        }
+
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!(format!("{} is not a file", filename)))?;
+        let tmp_path = path.with_file_name(format!(
+            ".{}.{}.tmp",
+            file_name.to_string_lossy(),
+            std::process::id()
+        ));
+        if let Err(err) = write_new_file(&tmp_path, content, existing) {
+            let _ = fs::remove_file(&tmp_path);
+            bail!(format!("could not write {}: {}", filename, err));
+        }
+        Ok(Self {
+            filename: filename.to_string(),
+            path,
+            tmp_path,
+        })
+    }
+
+    /// renames the temporary file over the file it replaces
+    fn commit(self) -> anyhow::Result<()> {
+        if let Err(err) = fs::rename(&self.tmp_path, &self.path) {
+            self.discard();
+            bail!(format!("could not write {}: {}", self.filename, err));
+        }
+        Ok(())
+    }
+
+    fn discard(&self) {
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+/// writes `content` to a temporary file next to `filename` and renames it over `filename`,
+/// so that `filename` is never left half written
+fn write_file(filename: &str, content: &str, backup: bool) -> anyhow::Result<()> {
+    PendingFile::write(filename, content, backup)?.commit()
+}
+
+pub(crate) fn write_flake(filename: &str, flake: &Flake, backup: bool) -> anyhow::Result<()> {
+    write_file(filename, &flake.to_string(), backup)
+}
+
+/// writes the flake, and the lock file next to it if given
+/// both are written before either replaces what is on disk,
+/// so that a failure leaves the two of them as they were
+pub(crate) fn write_flake_and_lock(
+    filename: &str,
+    flake: &Flake,
+    lock: Option<&FlakeLock>,
+    backup: bool,
+) -> anyhow::Result<()> {
+    let flake_file = PendingFile::write(filename, &flake.to_string(), backup)?;
+    let lock_file = match lock {
+        Some(lock) => match PendingFile::write(&lock_filename(filename), &lock.to_json(), backup) {
+            Ok(lock_file) => Some(lock_file),
+            Err(err) => {
+                flake_file.discard();
+                return Err(err);
+            }
+        },
+        None => None,
+    };
+    if let Err(err) = flake_file.commit() {
+        if let Some(lock_file) = &lock_file {
+            lock_file.discard();
+        }
+        return Err(err);
+    }
+    match lock_file {
+        // renaming hardly fails once writing worked, but the flake is already replaced then
+        Some(lock_file) => lock_file
+            .commit()
+            .map_err(|err| anyhow!(format!("{}, though {} was written", err, filename))),
+        None => Ok(()),
+    }
+}
+
+/// writes `content` to a new file at `path`, with the permissions of `existing` if any
+fn write_new_file(path: &Path, content: &str, existing: Option<fs::Metadata>) -> io::Result<()> {
     let mut file = fs::OpenOptions::new()
         .write(true)
-        .truncate(true)
-        .open(user_data.filename.as_ref().unwrap())
-        .unwrap();
-    file.write_all(stringified.as_bytes()).unwrap();
+        .create_new(true)
+        .open(path)?;
+    file.write_all(content.as_bytes())?;
+    if let Some(metadata) = existing {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()
+}
+
+/// formats the whole flake at `filename`, e.g. after it was generated
+pub(crate) fn format_flake_file(filename: &str) -> anyhow::Result<()> {
+    let flake = Flake::new(filename_to_node(filename, &SmolStr::new(filename))?);
+    write_flake(filename, &flake.format(), false)
+}
+
+/// writes the flake, with the edited lines formatted, and the lock file if it changed
+pub(crate) fn write_to_node(user_data: &UserMetadata, backup: bool) -> anyhow::Result<()> {
+    match (&user_data.filename, user_data.flake_to_write()) {
+        (Some(filename), Some(flake)) => {
+            let lock = user_data
+                .lock
+                .as_ref()
+                .filter(|_| user_data.lock != user_data.original_lock);
+            write_flake_and_lock(filename, &flake, lock, backup)
+        }
+        _ => bail!("no flake loaded, there is nothing to write"),
+    }
 }
@@ -0,0 +1,64 @@
+use crate::parser::file::write_flake_and_lock;
+use crate::parser::flake::Flake;
+use crate::parser::lock::FlakeLock;
+
+use std::fs;
+use std::path::PathBuf;
+
+const FLAKE: &str = include_str!("../../test_data/inputs.nix");
+const LOCK: &str = include_str!("../../test_data/inputs.lock");
+
+/// a directory of its own for a test, holding flake.nix and flake.lock
+fn flake_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("flake_generator_{}_{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("flake.nix"), FLAKE).unwrap();
+    fs::write(dir.join("flake.lock"), LOCK).unwrap();
+    dir
+}
+
+fn without_hello() -> (Flake, FlakeLock) {
+    let flake = Flake::parse(FLAKE.to_string())
+        .unwrap()
+        .remove_input("hello")
+        .unwrap();
+    let lock = FlakeLock::parse(LOCK).unwrap().remove_input("hello");
+    (flake, lock)
+}
+
+#[test]
+pub fn write_flake_and_lock_together() {
+    let dir = flake_dir("write_both");
+    let filename = dir.join("flake.nix").to_string_lossy().to_string();
+    let (flake, lock) = without_hello();
+    write_flake_and_lock(&filename, &flake, Some(&lock), false).unwrap();
+    assert_eq!(fs::read_to_string(&filename).unwrap(), flake.to_string());
+    assert_eq!(
+        fs::read_to_string(dir.join("flake.lock")).unwrap(),
+        lock.to_json()
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn failing_lock_leaves_the_flake() {
+    let dir = flake_dir("lock_fails");
+    let filename = dir.join("flake.nix").to_string_lossy().to_string();
+    // the temporary file of the lock can't be created when one is in the way
+    let in_the_way = dir.join(format!(".flake.lock.{}.tmp", std::process::id()));
+    fs::create_dir(&in_the_way).unwrap();
+    let (flake, lock) = without_hello();
+    let err = write_flake_and_lock(&filename, &flake, Some(&lock), false).unwrap_err();
+    assert!(err.to_string().starts_with("could not write"));
+    assert_eq!(fs::read_to_string(&filename).unwrap(), FLAKE);
+    assert_eq!(fs::read_to_string(dir.join("flake.lock")).unwrap(), LOCK);
+    // nothing is left behind
+    let leftovers = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".tmp") && name.starts_with(".flake.nix"))
+        .collect::<Vec<_>>();
+    assert!(leftovers.is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,855 @@
+use crate::parser::error::{AstError, Span};
+use crate::parser::flake_ref::{FlakeRef, FlakeRefError, FlakeRefKind};
+use crate::parser::format::{changed_lines, format, format_range};
+use crate::parser::lock::FlakeLock;
+use crate::parser::utils::{
+    add_dependency, add_follows, add_input, attr_name, change_input_url, derivation_calls,
+    get_output_fn, key_path, kill_node_attribute, leading_comments, line_indent, list_dependencies,
+    make_inputs_follow, remove_dependency, remove_follows, remove_input_from_output_fn,
+    rename_input, replace_entries_with_text, replace_input_ref, retarget_follows, string_literal,
+    string_to_node, to_nix_string, top_level_attrs, trailing_comment, unused_inputs, NixNode,
+    NixToken,
+};
+use anyhow::{anyhow, bail};
+use parse_display::{Display, FromStr};
+use rnix::{types::*, SyntaxKind::*};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Typed view of a flake.nix
+/// Nothing is cached: every query walks the underlying rowan tree,
+/// and every edit returns a new `Flake` since rowan trees are immutable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flake {
+    root: NixNode,
+}
+
+/// An input of a flake, along with all the attributes set on it.
+#[derive(Debug, Clone)]
+pub struct FlakeInput {
+    name: String,
+    /// attribute path relative to the input, e.g. `["inputs", "nixpkgs", "follows"]`,
+    /// and the node of its value
+    attrs: Vec<(Vec<String>, NixNode)>,
+}
+
+/// `input.inputs.nested.follows = "target";`
+#[derive(Debug, Clone, PartialEq, Eq, Display, FromStr)]
+#[display("{input}.inputs.{nested} follows {target}")]
+pub struct Follows {
+    pub input: String,
+    pub nested: String,
+    pub target: String,
+}
+
+/// Attribute of a derivation listing its dependencies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, FromStr)]
+pub enum DepAttr {
+    /// dependencies needed at run time, e.g. libraries to link against
+    #[display("buildInputs")]
+    BuildInputs,
+    /// dependencies needed to build, e.g. `pkg-config`
+    #[display("nativeBuildInputs")]
+    NativeBuildInputs,
+    /// packages available in a `mkShell`
+    #[display("packages")]
+    Packages,
+}
+
+/// A list of dependencies of a derivation of the outputs, which may not be written yet
+/// e.g. `devShell buildInputs` for `devShell = pkgs.mkShell { buildInputs = [ ... ]; };`
+#[derive(Debug, Clone, PartialEq, Eq, Display, FromStr)]
+#[display("{derivation} {attr}")]
+pub struct DepList {
+    /// name of the derivation, see `utils::derivation_calls`
+    pub derivation: String,
+    pub attr: DepAttr,
+}
+
+/// A dependency of a derivation of the outputs, as it is written, e.g. `pkgs.openssl`
+#[derive(Debug, Clone, PartialEq, Eq, Display, FromStr)]
+#[display("{derivation} {attr} {name}")]
+pub struct Dep {
+    pub derivation: String,
+    pub attr: DepAttr,
+    pub name: String,
+}
+
+/// How the reference of an input is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, FromStr)]
+pub enum InputStyle {
+    /// `foo.url = "github:owner/repo";`
+    #[display("url")]
+    Url,
+    /// `foo = { type = "github"; owner = "owner"; repo = "repo"; };`
+    #[display("attrs")]
+    Attrs,
+}
+
+/// How the inputs are laid out in the flake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, FromStr)]
+pub enum InputsLayout {
+    /// `inputs = { foo.url = "..."; bar = { url = "..."; flake = false; }; };`
+    #[display("nested")]
+    Nested,
+    /// `inputs = { foo = { url = "..."; }; };`
+    #[display("sets")]
+    Sets,
+    /// `inputs.foo.url = "...";`
+    #[display("dotted")]
+    Dotted,
+}
+
+/// an attribute of an input, with the comments around it, as written by `normalize_inputs`
+struct LaidOutAttr {
+    path: Vec<String>,
+    value: String,
+    comments: Vec<String>,
+    trailing: Option<String>,
+}
+
+/// an input, with the comments above it, as written by `normalize_inputs`
+struct LaidOutInput {
+    name: String,
+    comments: Vec<String>,
+    attrs: Vec<LaidOutAttr>,
+}
+
+/// where an attribute of an input goes: its reference first, then whether it's a flake,
+/// then what it follows
+fn attr_rank(path: &[String]) -> usize {
+    match path {
+        [name] if name == "flake" => 1,
+        [name] if name == "follows" => 2,
+        [name] if !NON_REF_ATTRS.contains(&name.as_str()) => 0,
+        _ => 3,
+    }
+}
+
+/// the lines of the inputs laid out as `layout`, every line indented with `indent`
+fn layout_lines(inputs: &[LaidOutInput], layout: InputsLayout, indent: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let attr_line = |lines: &mut Vec<String>, indent: &str, prefix: &str, attr: &LaidOutAttr| {
+        for comment in &attr.comments {
+            lines.push(format!("{}{}", indent, comment));
+        }
+        let path = attr
+            .path
+            .iter()
+            .map(|name| attr_name(name))
+            .collect::<Vec<_>>();
+        let mut line = format!("{}{}{} = {};", indent, prefix, path.join("."), attr.value);
+        if let Some(trailing) = &attr.trailing {
+            line.push(' ');
+            line.push_str(trailing);
+        }
+        lines.push(line);
+    };
+    let inner = format!("{}  ", indent);
+    if layout != InputsLayout::Dotted {
+        lines.push(format!("{}inputs = {{", indent));
+    }
+    for input in inputs {
+        let name = attr_name(&input.name);
+        let input_indent = if layout == InputsLayout::Dotted {
+            indent
+        } else {
+            &inner
+        };
+        for comment in &input.comments {
+            lines.push(format!("{}{}", input_indent, comment));
+        }
+        match layout {
+            InputsLayout::Dotted => {
+                for attr in &input.attrs {
+                    attr_line(&mut lines, indent, &format!("inputs.{}.", name), attr);
+                }
+            }
+            InputsLayout::Nested if input.attrs.len() == 1 => {
+                attr_line(&mut lines, &inner, &format!("{}.", name), &input.attrs[0]);
+            }
+            _ => {
+                lines.push(format!("{}{} = {{", inner, name));
+                for attr in &input.attrs {
+                    attr_line(&mut lines, &format!("{}  ", inner), "", attr);
+                }
+                lines.push(format!("{}}};", inner));
+            }
+        }
+    }
+    if layout != InputsLayout::Dotted {
+        lines.push(format!("{}}};", indent));
+    }
+    lines
+}
+
+/// attributes of an input that aren't part of its reference
+const NON_REF_ATTRS: [&str; 3] = ["flake", "inputs", "follows"];
+/// parameters written as booleans in attribute sets, and as `1` or `0` in urls
+const BOOL_PARAMS: [&str; 3] = ["shallow", "submodules", "allRefs"];
+
+/// A revision to pin an input to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pin {
+    Rev(String),
+    /// a tag, along with the commit it points to
+    Tag {
+        name: String,
+        rev: String,
+    },
+}
+
+/// `url`, pinned to `pin`, keeping its other parameters
+/// local paths become `git+file:` urls since `path:` can't be pinned,
+/// and tags of git urls are pinned to both the tag and its commit
+/// `git+file:` urls must be absolute, so relative paths are resolved from `flake_dir`
+pub fn pinned_url(url: &str, pin: &Pin, flake_dir: &Path) -> anyhow::Result<String> {
+    let mut flake_ref =
+        FlakeRef::from_str(url).map_err(|err| anyhow!(format!("invalid url {}: {}", url, err)))?;
+    let local_path = match &flake_ref.kind {
+        FlakeRefKind::Path { path } => Some(path.as_str()),
+        FlakeRefKind::Git { url } => url.strip_prefix("file:"),
+        _ => None,
+    };
+    if let Some(path) = local_path {
+        // `file:///foo` and `file:/foo` are the same
+        let path = path.strip_prefix("//").unwrap_or(path);
+        // joining drops the `.` components, but an absolute path replaces `flake_dir`
+        let path = flake_dir
+            .join(path)
+            .components()
+            .collect::<std::path::PathBuf>();
+        if !path.is_absolute() {
+            bail!(
+                "{} can't be pinned, as the directory of the flake is unknown",
+                url
+            );
+        }
+        flake_ref.kind = FlakeRefKind::Git {
+            url: format!("file://{}", path.display()),
+        };
+    }
+    let (git_ref, rev) = match (&flake_ref.kind, pin) {
+        (FlakeRefKind::Tarball { .. }, _) => bail!("{} can't be pinned to a revision", url),
+        (_, Pin::Rev(rev)) => (None, Some(rev.clone())),
+        (FlakeRefKind::Git { .. }, Pin::Tag { name, rev }) => {
+            (Some(format!("refs/tags/{}", name)), Some(rev.clone()))
+        }
+        (_, Pin::Tag { name, .. }) => (Some(name.clone()), None),
+    };
+    flake_ref.git_ref = git_ref;
+    flake_ref.rev = rev;
+    Ok(flake_ref.to_string())
+}
+
+/// flattens nested attribute sets into (path, value) pairs
+/// e.g. `{ a = { b = 1; }; c.d = 2; }` gives `[(["a", "b"], 1), (["c", "d"], 2)]`
+fn flatten_attrs(path: Vec<String>, value: NixNode, out: &mut Vec<(Vec<String>, NixNode)>) {
+    match AttrSet::cast(value.clone()) {
+        Some(attr_set) if !attr_set.recursive() => {
+            for entry in attr_set.entries() {
+                if let Some(entry_value) = entry.value() {
+                    let mut entry_path = path.clone();
+                    entry_path.extend(key_path(&entry));
+                    flatten_attrs(entry_path, entry_value, out);
+                }
+            }
+        }
+        _ => out.push((path, value)),
+    }
+}
+
+/// the outermost attribute that only sets things on a single input,
+/// e.g. the whole of `inputs.foo = { url = "..."; };` for the node of the url
+fn input_entry(value: &NixNode) -> Option<NixNode> {
+    let entries = value
+        .ancestors()
+        .filter_map(KeyValue::cast)
+        .collect::<Vec<_>>();
+    // the path from the top level attribute set to each entry, outermost first
+    let mut depth = 0;
+    entries
+        .iter()
+        .rev()
+        .find(|entry| {
+            depth += key_path(entry).len();
+            // inputs.{}
+            depth >= 2
+        })
+        .map(|entry| entry.node().clone())
+}
+
+impl Flake {
+    pub fn new(root: NixNode) -> Self {
+        Self { root }
+    }
+
+    pub fn parse(content: String) -> anyhow::Result<Self> {
+        string_to_node(content).map(Self::new)
+    }
+
+    /// the whole flake formatted, see `format::format`
+    pub fn format(&self) -> Self {
+        Self::new(format(&self.root))
+    }
+
+    /// the flake with the lines that differ from `original` formatted
+    pub fn format_changes(&self, original: &str) -> Self {
+        match changed_lines(original, &self.to_string()) {
+            Some(range) => Self::new(format_range(&self.root, Some(range))),
+            None => self.clone(),
+        }
+    }
+
+    pub fn root(&self) -> &NixNode {
+        &self.root
+    }
+
+    fn top_level_attr(&self, name: &str) -> Option<NixNode> {
+        top_level_attrs(&self.root)?
+            .entries()
+            .find(|entry| key_path(entry) == [name])
+            .and_then(|entry| entry.value())
+    }
+
+    pub fn description(&self) -> Option<String> {
+        self.top_level_attr("description")
+            .and_then(|node| string_literal(&node))
+    }
+
+    /// all inputs, in the order they first appear in
+    pub fn inputs(&self) -> Vec<FlakeInput> {
+        let mut attrs = Vec::new();
+        if let Some(top_level) = top_level_attrs(&self.root) {
+            for entry in top_level.entries() {
+                let path = key_path(&entry);
+                if let (Some("inputs"), Some(value)) =
+                    (path.first().map(String::as_str), entry.value())
+                {
+                    flatten_attrs(path[1..].to_vec(), value, &mut attrs);
+                }
+            }
+        }
+
+        let mut inputs: Vec<FlakeInput> = Vec::new();
+        for (mut path, value) in attrs {
+            if path.is_empty() {
+                continue;
+            }
+            let name = path.remove(0);
+            match inputs.iter_mut().find(|input| input.name == name) {
+                Some(input) => input.attrs.push((path, value)),
+                None => inputs.push(FlakeInput {
+                    name,
+                    attrs: vec![(path, value)],
+                }),
+            }
+        }
+        inputs
+    }
+
+    pub fn input(&self, name: &str) -> Option<FlakeInput> {
+        self.inputs().into_iter().find(|input| input.name == name)
+    }
+
+    /// every follows of every input
+    pub fn follows(&self) -> Vec<Follows> {
+        self.inputs().iter().flat_map(FlakeInput::follows).collect()
+    }
+
+    /// the outputs function, even if it's bound by a `let ... in`
+    pub fn outputs(&self) -> Option<Lambda> {
+        get_output_fn(&self.root).ok()
+    }
+
+    /// names of the arguments the outputs function destructures,
+    /// or `None` if it takes its inputs as a single argument
+    pub fn output_args(&self) -> Option<Vec<String>> {
+        let pattern = Pattern::cast(self.outputs()?.arg()?)?;
+        Some(
+            pattern
+                .entries()
+                .filter_map(|entry| entry.name())
+                .map(|name| name.as_str().to_string())
+                .collect(),
+        )
+    }
+
+    pub fn output_body(&self) -> Option<NixNode> {
+        self.outputs()?.body()
+    }
+
+    pub fn add_input(&self, name: &str, url: &str, is_flake: bool) -> anyhow::Result<Self> {
+        add_input(&self.root, name, url, is_flake).map(Self::new)
+    }
+
+    /// removes every attribute of the input, and the input from the outputs arguments
+    pub fn remove_input(&self, name: &str) -> anyhow::Result<Self> {
+        let mut root = self.root.clone();
+        let mut input = self
+            .input(name)
+            .ok_or_else(|| AstError::InputNotFound(name.to_string()))?;
+        while let Some(entry) = input.attrs.first().and_then(|(_, node)| input_entry(node)) {
+            root = kill_node_attribute(&entry, 1)?;
+            input = match Self::new(root.clone()).input(name) {
+                Some(input) => input,
+                None => break,
+            };
+        }
+        remove_input_from_output_fn(root, name).map(Self::new)
+    }
+
+    /// names of the inputs nothing refers to, see `utils::unused_inputs`
+    pub fn unused_inputs(&self) -> anyhow::Result<Vec<String>> {
+        unused_inputs(&self.root)
+    }
+
+    pub fn remove_unused_inputs(&self) -> anyhow::Result<Self> {
+        self.unused_inputs()?
+            .iter()
+            .try_fold(self.clone(), |flake, name| flake.remove_input(name))
+    }
+
+    /// renames the input, its arguments in the outputs and everything referring to it
+    pub fn rename_input(&self, old_name: &str, new_name: &str) -> anyhow::Result<Self> {
+        rename_input(&self.root, old_name, new_name).map(Self::new)
+    }
+
+    /// rewrites every input in the same layout, sorted by name
+    /// comments go along with the input or attribute they are written above or after,
+    /// the others are kept above the inputs
+    pub fn normalize_inputs(&self, layout: InputsLayout) -> anyhow::Result<Self> {
+        let top_level = top_level_attrs(&self.root)
+            .ok_or_else(|| anyhow!("the flake is not an attribute set"))?;
+        let dead = top_level
+            .entries()
+            .filter(|entry| key_path(entry).first().map(String::as_str) == Some("inputs"))
+            .map(|entry| entry.node().clone())
+            .collect::<Vec<_>>();
+        if dead.is_empty() {
+            return Ok(self.clone());
+        }
+        // anything `inputs()` doesn't see would be lost
+        for entry in &dead {
+            let unsupported = entry.descendants().find(|node| {
+                node.kind() == NODE_INHERIT
+                    || AttrSet::cast(node.clone()).map_or(false, |set| set.recursive())
+            });
+            if let Some(node) = unsupported {
+                return Err(AstError::UnsupportedSyntax(
+                    "inputs written this way".to_string(),
+                    Span::of(&node),
+                )
+                .into());
+            }
+        }
+
+        let mut assigned = Vec::new();
+        // comments above and after `node` that no other attribute took yet
+        let mut comments_of = |node: &NixNode| {
+            let mut take = |comment: NixToken| {
+                let offset = comment.text_range().start();
+                if assigned.contains(&offset) {
+                    None
+                } else {
+                    assigned.push(offset);
+                    Some(comment.text().to_string())
+                }
+            };
+            let leading = leading_comments(node)
+                .into_iter()
+                .filter_map(&mut take)
+                .collect::<Vec<_>>();
+            (leading, trailing_comment(node).and_then(take))
+        };
+
+        let mut inputs = Vec::new();
+        for input in self.inputs() {
+            let mut laid_out = LaidOutInput {
+                name: input.name.clone(),
+                comments: Vec::new(),
+                attrs: Vec::new(),
+            };
+            for (idx, (path, value)) in input.attrs.iter().enumerate() {
+                let leaf = match value.parent() {
+                    Some(leaf) if !path.is_empty() => leaf,
+                    _ => {
+                        return Err(AstError::UnsupportedSyntax(
+                            "input that isn't an attribute set".to_string(),
+                            Span::of(value),
+                        )
+                        .into())
+                    }
+                };
+                let mut attr = LaidOutAttr {
+                    path: path.clone(),
+                    value: value.to_string(),
+                    comments: Vec::new(),
+                    trailing: None,
+                };
+                let mut entries = value
+                    .ancestors()
+                    .take_while(|node| node != top_level.node())
+                    .filter_map(KeyValue::cast)
+                    .collect::<Vec<_>>();
+                entries.reverse();
+                let mut depth = 0;
+                for entry in entries {
+                    let names_input = depth <= 1;
+                    depth += key_path(&entry).len();
+                    // comments of the whole `inputs = { ... };` stay above the inputs
+                    if depth <= 1 {
+                        continue;
+                    }
+                    let (leading, trailing) = comments_of(entry.node());
+                    if *entry.node() == leaf {
+                        // a comment above `foo.url = ...;` is about foo
+                        if names_input && idx == 0 {
+                            laid_out.comments.extend(leading);
+                        } else {
+                            attr.comments.extend(leading);
+                        }
+                        attr.trailing = trailing;
+                    } else if depth == 2 {
+                        laid_out.comments.extend(leading);
+                        laid_out.comments.extend(trailing);
+                    } else {
+                        attr.comments.extend(leading);
+                        attr.comments.extend(trailing);
+                    }
+                }
+                laid_out.attrs.push(attr);
+            }
+            laid_out.attrs.sort_by_key(|attr| attr_rank(&attr.path));
+            inputs.push(laid_out);
+        }
+        inputs.sort_by(|a, b| {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        let indent = line_indent(&dead[0]);
+        let mut lines = Vec::new();
+        for entry in &dead {
+            let mut comments = leading_comments(entry);
+            comments.extend(
+                entry
+                    .descendants_with_tokens()
+                    .filter_map(|element| element.into_token())
+                    .filter(|tok| tok.kind() == TOKEN_COMMENT),
+            );
+            comments.extend(trailing_comment(entry));
+            for comment in comments {
+                if !assigned.contains(&comment.text_range().start()) {
+                    lines.push(format!("{}{}", indent, comment.text()));
+                }
+            }
+        }
+        lines.extend(layout_lines(&inputs, layout, &indent));
+        replace_entries_with_text(top_level.node(), &dead, &lines.join("\n")).map(Self::new)
+    }
+
+    /// changes the url of the input, keeping it an attribute set if it is written as one
+    pub fn set_input_url(&self, name: &str, url: &str) -> anyhow::Result<Self> {
+        match self.input(name) {
+            Some(input) if input.style() == Some(InputStyle::Attrs) => {
+                let flake_ref = FlakeRef::from_str(url)
+                    .map_err(|err| anyhow!(format!("invalid url {}: {}", url, err)))?;
+                self.write_input_ref(&input, &flake_ref, InputStyle::Attrs)
+            }
+            _ => change_input_url(&self.root, &format!(".inputs.{}.url", name), url, None)
+                .map(Self::new),
+        }
+    }
+
+    /// rewrites the reference of the input in the given style
+    /// nothing changes if it is already written that way
+    pub fn convert_input(&self, name: &str, style: InputStyle) -> anyhow::Result<Self> {
+        let input = self
+            .input(name)
+            .ok_or_else(|| AstError::InputNotFound(name.to_string()))?;
+        if input.style() == Some(style) {
+            return Ok(self.clone());
+        }
+        let flake_ref = input
+            .flake_ref()
+            .ok_or_else(|| anyhow!(format!("input {} has no url", name)))?
+            .map_err(|err| anyhow!(format!("input {} has an invalid url: {}", name, err)))?;
+        self.write_input_ref(&input, &flake_ref, style)
+    }
+
+    /// rewrites the reference of every input in the given style,
+    /// skipping the ones that only follow another input
+    pub fn convert_inputs(&self, style: InputStyle) -> anyhow::Result<Self> {
+        self.inputs()
+            .iter()
+            .filter(|input| input.style().is_some())
+            .try_fold(self.clone(), |flake, input| {
+                flake.convert_input(input.name(), style)
+            })
+    }
+
+    fn write_input_ref(
+        &self,
+        input: &FlakeInput,
+        flake_ref: &FlakeRef,
+        style: InputStyle,
+    ) -> anyhow::Result<Self> {
+        let attrs = match style {
+            InputStyle::Url => vec![("url".to_string(), to_nix_string(&flake_ref.to_string()))],
+            InputStyle::Attrs => flake_ref
+                .to_attrs()
+                .into_iter()
+                .map(|(name, value)| {
+                    let value = match value.as_str() {
+                        "1" | "true" if BOOL_PARAMS.contains(&name.as_str()) => "true".to_string(),
+                        "0" | "false" if BOOL_PARAMS.contains(&name.as_str()) => {
+                            "false".to_string()
+                        }
+                        _ if (name == "lastModified" || name == "revCount")
+                            && value.chars().all(|c| c.is_ascii_digit()) =>
+                        {
+                            value
+                        }
+                        _ => to_nix_string(&value),
+                    };
+                    (name, value)
+                })
+                .collect(),
+        };
+        let old_entries = input
+            .ref_attrs()
+            .iter()
+            .filter_map(|(_, node)| node.parent())
+            .collect::<Vec<_>>();
+        replace_input_ref(&self.root, &old_entries, &attrs).map(Self::new)
+    }
+
+    /// pins the input to a revision, changing nothing but its url
+    /// local paths are resolved from `flake_dir`, see `pinned_url`
+    pub fn pin_input(&self, name: &str, pin: &Pin, flake_dir: &Path) -> anyhow::Result<Self> {
+        let url = self
+            .input(name)
+            .ok_or_else(|| AstError::InputNotFound(name.to_string()))?
+            .url()
+            .ok_or_else(|| anyhow!(format!("input {} has no url", name)))?;
+        self.set_input_url(name, &pinned_url(&url, pin, flake_dir)?)
+    }
+
+    pub fn add_follows(&self, follows: &Follows) -> anyhow::Result<Self> {
+        add_follows(&self.root, &follows.input, &follows.nested, &follows.target).map(Self::new)
+    }
+
+    pub fn remove_follows(&self, follows: &Follows) -> anyhow::Result<Self> {
+        remove_follows(&self.root, &follows.path()).map(Self::new)
+    }
+
+    pub fn retarget_follows(&self, follows: &Follows, target: &str) -> anyhow::Result<Self> {
+        retarget_follows(&self.root, &follows.path(), target).map(Self::new)
+    }
+
+    /// makes every input follow `target` for its own input of the same name
+    /// with a lock file, only the inputs locked with such an input are changed,
+    /// as nix warns about overriding an input that doesn't exist
+    pub fn follow_everywhere(
+        &self,
+        target: &str,
+        lock: Option<&FlakeLock>,
+    ) -> anyhow::Result<Self> {
+        let only = lock.map(|lock| lock.inputs_with(target));
+        make_inputs_follow(&self.root, target, only.as_deref()).map(Self::new)
+    }
+
+    /// the lists of dependencies every derivation of the outputs can have, written or not
+    pub fn dep_lists(&self) -> Vec<DepList> {
+        derivation_calls(&self.root)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(derivation, builder, _)| {
+                DepAttr::ALL
+                    .iter()
+                    .filter(move |attr| **attr != DepAttr::Packages || builder == "mkShell")
+                    .map(move |attr| DepList {
+                        derivation: derivation.clone(),
+                        attr: *attr,
+                    })
+            })
+            .collect()
+    }
+
+    /// the dependencies of every derivation of the outputs
+    pub fn deps(&self) -> Vec<Dep> {
+        derivation_calls(&self.root)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(derivation, _, attrs)| {
+                DepAttr::ALL.iter().flat_map(move |attr| {
+                    let derivation = derivation.clone();
+                    list_dependencies(&attrs, &attr.to_string())
+                        .into_iter()
+                        .map(move |name| Dep {
+                            derivation: derivation.clone(),
+                            attr: *attr,
+                            name,
+                        })
+                })
+            })
+            .collect()
+    }
+
+    /// the attributes given to the builder of a derivation of the outputs
+    fn derivation_attrs(&self, derivation: &str) -> anyhow::Result<NixNode> {
+        derivation_calls(&self.root)?
+            .into_iter()
+            .find(|(name, _, _)| name == derivation)
+            .map(|(_, _, attrs)| attrs)
+            .ok_or_else(|| AstError::DerivationNotFound(derivation.to_string()).into())
+    }
+
+    /// adds the package `package` of nixpkgs to a list of dependencies
+    pub fn add_dep(&self, list: &DepList, package: &str) -> anyhow::Result<Self> {
+        if !self.dep_lists().contains(list) {
+            bail!("{} has no {}", list.derivation, list.attr);
+        }
+        let attrs = self.derivation_attrs(&list.derivation)?;
+        add_dependency(&attrs, &list.attr.to_string(), package).map(Self::new)
+    }
+
+    pub fn remove_dep(&self, dep: &Dep) -> anyhow::Result<Self> {
+        let attrs = self.derivation_attrs(&dep.derivation)?;
+        remove_dependency(&attrs, &dep.attr.to_string(), &dep.name).map(Self::new)
+    }
+}
+
+impl fmt::Display for Flake {
+    /// the whole file, with the comments and whitespace around the expression
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.root.ancestors().last() {
+            Some(file) => write!(f, "{}", file),
+            None => write!(f, "{}", self.root),
+        }
+    }
+}
+
+impl FlakeInput {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn attr(&self, path: &[&str]) -> Option<&NixNode> {
+        self.attrs
+            .iter()
+            .find(|(attr_path, _)| {
+                attr_path
+                    .iter()
+                    .map(String::as_str)
+                    .eq(path.iter().copied())
+            })
+            .map(|(_, node)| node)
+    }
+
+    /// the `url` attribute, only part of the reference of inputs written as attribute sets
+    pub fn url_node(&self) -> Option<&NixNode> {
+        self.attr(&["url"])
+    }
+
+    /// the url of the input, built from its attributes if it is written as an attribute set
+    pub fn url(&self) -> Option<String> {
+        match self.style()? {
+            InputStyle::Url => string_literal(self.url_node()?),
+            InputStyle::Attrs => Some(self.flake_ref()?.ok()?.to_string()),
+        }
+    }
+
+    /// the attributes saying where the input comes from, e.g. `url` or `type`, `owner`...
+    fn ref_attrs(&self) -> Vec<(&str, &NixNode)> {
+        self.attrs
+            .iter()
+            .filter_map(|(path, node)| match path.as_slice() {
+                [name] if !NON_REF_ATTRS.contains(&name.as_str()) => Some((name.as_str(), node)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// how the reference of the input is written, if it has one
+    pub fn style(&self) -> Option<InputStyle> {
+        if self.attr(&["type"]).is_some() {
+            Some(InputStyle::Attrs)
+        } else if self.url_node().is_some() {
+            Some(InputStyle::Url)
+        } else {
+            None
+        }
+    }
+
+    /// the parsed reference of the input, from its url or its attributes
+    pub fn flake_ref(&self) -> Option<Result<FlakeRef, FlakeRefError>> {
+        match self.style()? {
+            InputStyle::Url => Some(FlakeRef::from_str(&string_literal(self.url_node()?)?)),
+            InputStyle::Attrs => {
+                let attrs = self
+                    .ref_attrs()
+                    .into_iter()
+                    .map(|(name, node)| {
+                        let value = string_literal(node).unwrap_or_else(|| {
+                            match node.to_string().as_str() {
+                                "true" => "1".to_string(),
+                                "false" => "0".to_string(),
+                                text => text.to_string(),
+                            }
+                        });
+                        (name.to_string(), value)
+                    })
+                    .collect::<Vec<_>>();
+                Some(FlakeRef::from_attrs(&attrs))
+            }
+        }
+    }
+
+    /// inputs are flakes unless marked with `flake = false`
+    pub fn is_flake(&self) -> bool {
+        self.attr(&["flake"]).map_or(true, |node| {
+            node.kind() != NODE_IDENT || node.to_string() != "false"
+        })
+    }
+
+    /// the input followed by this input itself, e.g. `inputs.foo.follows = "bar";`
+    pub fn follows_input(&self) -> Option<String> {
+        self.attr(&["follows"]).and_then(string_literal)
+    }
+
+    /// the follows of this input's own inputs
+    pub fn follows(&self) -> Vec<Follows> {
+        self.attrs
+            .iter()
+            .filter_map(|(path, node)| match path.as_slice() {
+                [inputs, nested, follows] if inputs == "inputs" && follows == "follows" => {
+                    Some(Follows {
+                        input: self.name.clone(),
+                        nested: nested.clone(),
+                        target: string_literal(node)?,
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl DepAttr {
+    const ALL: [DepAttr; 3] = [
+        DepAttr::BuildInputs,
+        DepAttr::NativeBuildInputs,
+        DepAttr::Packages,
+    ];
+}
+
+impl Follows {
+    fn path(&self) -> String {
+        format!(".inputs.{}.inputs.{}.follows", self.input, self.nested)
+    }
+}
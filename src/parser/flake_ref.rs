@@ -0,0 +1,482 @@
+use parse_display::Display;
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a flake reference points to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlakeRefKind {
+    /// `github:`, `gitlab:` and `sourcehut:` references
+    Forge {
+        forge: Forge,
+        owner: String,
+        repo: String,
+    },
+    /// `git+https:`, `git+ssh:`, `git+file:`... with the url git fetches, e.g. `https://host/repo.git`
+    Git { url: String },
+    /// `path:` references and bare paths
+    Path { path: String },
+    /// archives, with their url as written, query included, e.g. `tarball+https://host/a?b=c`
+    Tarball { url: String },
+    /// references looked up in the flake registry, e.g. `nixpkgs`
+    Indirect { id: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[display(style = "lowercase")]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    SourceHut,
+}
+
+/// A parsed flake reference, see `nix flake --help`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlakeRef {
+    pub kind: FlakeRefKind,
+    pub git_ref: Option<String>,
+    pub rev: Option<String>,
+    /// the other parameters, e.g. `dir`, in the order they were written
+    pub params: Vec<(String, String)>,
+}
+
+/// ways a flake reference can be malformed
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum FlakeRefError {
+    #[display("the url is empty")]
+    Empty,
+    #[display("unknown flake reference type `{0}`")]
+    UnknownType(String),
+    #[display("`{0}` needs an owner and a repository, e.g. github:NixOS/nixpkgs")]
+    MissingOwnerOrRepo(String),
+    #[display("`{0}` has too many path elements")]
+    TooManyElements(String),
+    #[display("`{0}` is not a valid flake id, ids start with a letter followed by letters, digits, `-` or `_`")]
+    InvalidId(String),
+    #[display("`{0}` is not a full commit hash, revisions are 40 hexadecimal characters")]
+    InvalidRev(String),
+    #[display("`{0}` is missing a path")]
+    MissingPath(String),
+    #[display("`{0}` is missing a host")]
+    MissingHost(String),
+    #[display("`{0}` is not an archive, use `tarball+{0}` if it is one")]
+    NotAnArchive(String),
+    #[display("invalid parameter `{0}`, expected `name=value`")]
+    InvalidParam(String),
+    #[display("{0} references don't support the `{1}` parameter")]
+    UnsupportedParam(String, String),
+    #[display("`{0}` is given twice")]
+    Duplicate(String),
+    #[display("{0} references can't have both a `ref` and a `rev`")]
+    RefAndRev(String),
+    #[display("inputs can't select an output, remove `#{0}`")]
+    Fragment(String),
+    #[display("the `{0}` attribute is missing")]
+    MissingAttribute(String),
+}
+
+impl std::error::Error for FlakeRefError {}
+
+/// parameters every kind of reference takes, they are only checked by nix
+const COMMON_PARAMS: [&str; 4] = ["narHash", "lastModified", "revCount", "dir"];
+const FORGE_PARAMS: [&str; 3] = ["ref", "rev", "host"];
+const GIT_PARAMS: [&str; 5] = ["ref", "rev", "shallow", "submodules", "allRefs"];
+const INDIRECT_PARAMS: [&str; 2] = ["ref", "rev"];
+/// extensions nix recognizes archives by
+const ARCHIVE_EXTENSIONS: [&str; 8] = [
+    ".zip", ".tar", ".tgz", ".tar.gz", ".tar.xz", ".tar.bz2", ".tar.zst", ".tar.lz",
+];
+
+fn is_rev(rev: &str) -> bool {
+    rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_archive(url: &str) -> bool {
+    let path = url.split('?').next().unwrap_or_default();
+    ARCHIVE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+fn is_flake_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// splits a `ref-or-rev` path element
+fn ref_or_rev(element: &str) -> (Option<String>, Option<String>) {
+    if is_rev(element) {
+        (None, Some(element.to_string()))
+    } else {
+        (Some(element.to_string()), None)
+    }
+}
+
+/// `/` separated path elements after the type, which must all be non-empty
+fn path_elements<'a>(url: &str, path: &'a str) -> Result<Vec<&'a str>, FlakeRefError> {
+    let elements = path.split('/').collect::<Vec<_>>();
+    if elements.iter().any(|element| element.is_empty()) {
+        return Err(FlakeRefError::MissingOwnerOrRepo(url.to_string()));
+    }
+    Ok(elements)
+}
+
+impl FlakeRefKind {
+    /// the `type` attribute of references of this kind
+    pub fn type_name(&self) -> String {
+        match self {
+            FlakeRefKind::Forge { forge, .. } => forge.to_string(),
+            FlakeRefKind::Git { .. } => "git".to_string(),
+            FlakeRefKind::Path { .. } => "path".to_string(),
+            FlakeRefKind::Tarball { .. } => "tarball".to_string(),
+            FlakeRefKind::Indirect { .. } => "indirect".to_string(),
+        }
+    }
+
+    fn supported_params(&self) -> &'static [&'static str] {
+        match self {
+            FlakeRefKind::Forge { .. } => &FORGE_PARAMS,
+            FlakeRefKind::Git { .. } => &GIT_PARAMS,
+            FlakeRefKind::Indirect { .. } => &INDIRECT_PARAMS,
+            // any parameter of a tarball is passed on to the server
+            FlakeRefKind::Path { .. } | FlakeRefKind::Tarball { .. } => &[],
+        }
+    }
+}
+
+impl FlakeRef {
+    fn new(kind: FlakeRefKind) -> Self {
+        Self {
+            kind,
+            git_ref: None,
+            rev: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// sets a parameter, checking it makes sense for this kind of reference
+    fn set_param(&mut self, name: &str, value: &str) -> Result<(), FlakeRefError> {
+        let passed_on = matches!(self.kind, FlakeRefKind::Tarball { .. });
+        if !passed_on
+            && !COMMON_PARAMS.contains(&name)
+            && !self.kind.supported_params().contains(&name)
+        {
+            return Err(FlakeRefError::UnsupportedParam(
+                self.kind.type_name(),
+                name.to_string(),
+            ));
+        }
+        let duplicate = match name {
+            "ref" if !passed_on => self.git_ref.replace(value.to_string()).is_some(),
+            "rev" if !passed_on => {
+                if !is_rev(value) {
+                    return Err(FlakeRefError::InvalidRev(value.to_string()));
+                }
+                self.rev.replace(value.to_string()).is_some()
+            }
+            _ => {
+                let duplicate = self.params.iter().any(|(param, _)| param == name);
+                self.params.push((name.to_string(), value.to_string()));
+                duplicate && !passed_on
+            }
+        };
+        if duplicate {
+            return Err(FlakeRefError::Duplicate(name.to_string()));
+        }
+        // nix only accepts one of them for forges, the rev being a commit of the ref
+        if let FlakeRefKind::Forge { .. } = self.kind {
+            if self.git_ref.is_some() && self.rev.is_some() {
+                return Err(FlakeRefError::RefAndRev(self.kind.type_name()));
+            }
+        }
+        Ok(())
+    }
+
+    /// reads the attribute set form, e.g. `{ type = "github"; owner = "NixOS"; repo = "nixpkgs"; }`
+    /// the values are the strings, numbers or booleans as written
+    pub fn from_attrs(attrs: &[(String, String)]) -> Result<Self, FlakeRefError> {
+        let attr = |name: &str| {
+            attrs
+                .iter()
+                .find(|(attr, _)| attr == name)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| FlakeRefError::MissingAttribute(name.to_string()))
+        };
+        let type_name = attr("type")?;
+        let kind = match type_name.as_str() {
+            "github" | "gitlab" | "sourcehut" => FlakeRefKind::Forge {
+                forge: Forge::from_type(&type_name).unwrap_or(Forge::GitHub),
+                owner: attr("owner")?,
+                repo: attr("repo")?,
+            },
+            "git" => FlakeRefKind::Git { url: attr("url")? },
+            "path" => FlakeRefKind::Path {
+                path: attr("path")?,
+            },
+            "tarball" => {
+                let url = attr("url")?;
+                FlakeRefKind::Tarball {
+                    url: if is_archive(&url) {
+                        url
+                    } else {
+                        format!("tarball+{}", url)
+                    },
+                }
+            }
+            "indirect" => FlakeRefKind::Indirect { id: attr("id")? },
+            _ => return Err(FlakeRefError::UnknownType(type_name)),
+        };
+        let mut flake_ref = Self::new(kind);
+        let taken = ["type", "owner", "repo", "url", "path", "id"];
+        for (name, value) in attrs {
+            if !taken.contains(&name.as_str()) {
+                flake_ref.set_param(name, value)?;
+            }
+        }
+        Ok(flake_ref)
+    }
+
+    /// the attribute set form, in the order nix documents the attributes
+    pub fn to_attrs(&self) -> Vec<(String, String)> {
+        let mut attrs = vec![("type".to_string(), self.kind.type_name())];
+        match &self.kind {
+            FlakeRefKind::Forge { owner, repo, .. } => {
+                attrs.push(("owner".to_string(), owner.clone()));
+                attrs.push(("repo".to_string(), repo.clone()));
+            }
+            FlakeRefKind::Git { url } => attrs.push(("url".to_string(), url.clone())),
+            FlakeRefKind::Path { path } => attrs.push(("path".to_string(), path.clone())),
+            FlakeRefKind::Tarball { url } => attrs.push((
+                "url".to_string(),
+                url.strip_prefix("tarball+").unwrap_or(url).to_string(),
+            )),
+            FlakeRefKind::Indirect { id } => attrs.push(("id".to_string(), id.clone())),
+        }
+        if let Some(git_ref) = &self.git_ref {
+            attrs.push(("ref".to_string(), git_ref.clone()));
+        }
+        if let Some(rev) = &self.rev {
+            attrs.push(("rev".to_string(), rev.clone()));
+        }
+        attrs.extend(self.params.iter().cloned());
+        attrs
+    }
+
+    /// `base` followed by the parameters, `ref` and `rev` last unless they're in the path
+    fn with_params(&self, base: String, ref_in_path: bool) -> String {
+        let mut params = self
+            .params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>();
+        if !ref_in_path {
+            params.extend(
+                self.git_ref
+                    .iter()
+                    .map(|git_ref| format!("ref={}", git_ref)),
+            );
+            params.extend(self.rev.iter().map(|rev| format!("rev={}", rev)));
+        }
+        if params.is_empty() {
+            base
+        } else if base.contains('?') {
+            format!("{}&{}", base, params.join("&"))
+        } else {
+            format!("{}?{}", base, params.join("&"))
+        }
+    }
+}
+
+impl Forge {
+    fn from_type(type_name: &str) -> Option<Self> {
+        match type_name {
+            "github" => Some(Forge::GitHub),
+            "gitlab" => Some(Forge::GitLab),
+            "sourcehut" => Some(Forge::SourceHut),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for FlakeRef {
+    type Err = FlakeRefError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        if url.is_empty() {
+            return Err(FlakeRefError::Empty);
+        }
+        let (url, fragment) = match url.find('#') {
+            Some(idx) => (&url[..idx], &url[idx + 1..]),
+            None => (url, ""),
+        };
+        if !fragment.is_empty() {
+            return Err(FlakeRefError::Fragment(fragment.to_string()));
+        }
+        let (base, query) = match url.find('?') {
+            Some(idx) => (&url[..idx], &url[idx + 1..]),
+            None => (url, ""),
+        };
+        // the type is whatever comes before the first `:`, unless it's part of a path
+        let (type_name, path) = match base.find(':') {
+            Some(idx) if !base[..idx].contains('/') => (&base[..idx], &base[idx + 1..]),
+            _ if base.starts_with('/') || base.starts_with('.') => ("path", base),
+            _ => ("flake", base),
+        };
+
+        let (kind, path_ref) = match type_name {
+            "github" | "gitlab" | "sourcehut" => {
+                let elements = path_elements(url, path)?;
+                if elements.len() < 2 {
+                    return Err(FlakeRefError::MissingOwnerOrRepo(url.to_string()));
+                }
+                let kind = FlakeRefKind::Forge {
+                    forge: Forge::from_type(type_name).unwrap_or(Forge::GitHub),
+                    owner: elements[0].to_string(),
+                    repo: elements[1].to_string(),
+                };
+                // refs may contain slashes, e.g. github:owner/repo/release/1.0
+                let path_ref = if elements.len() > 2 {
+                    Some(elements[2..].join("/"))
+                } else {
+                    None
+                };
+                (kind, path_ref)
+            }
+            "flake" => {
+                let elements = path.split('/').collect::<Vec<_>>();
+                if !is_flake_id(elements[0]) {
+                    return Err(FlakeRefError::InvalidId(elements[0].to_string()));
+                }
+                if elements.len() > 3 {
+                    return Err(FlakeRefError::TooManyElements(url.to_string()));
+                }
+                let mut flake_ref = Self::new(FlakeRefKind::Indirect {
+                    id: elements[0].to_string(),
+                });
+                match &elements[1..] {
+                    [] => {}
+                    [element] => {
+                        let (git_ref, rev) = ref_or_rev(element);
+                        flake_ref.git_ref = git_ref;
+                        flake_ref.rev = rev;
+                    }
+                    [git_ref, rev] => {
+                        if !is_rev(rev) {
+                            return Err(FlakeRefError::InvalidRev(rev.to_string()));
+                        }
+                        flake_ref.git_ref = Some(git_ref.to_string());
+                        flake_ref.rev = Some(rev.to_string());
+                    }
+                    _ => unreachable!(),
+                }
+                return flake_ref.parse_query(query);
+            }
+            "path" => {
+                if path.is_empty() {
+                    return Err(FlakeRefError::MissingPath(url.to_string()));
+                }
+                (
+                    FlakeRefKind::Path {
+                        path: path.to_string(),
+                    },
+                    None,
+                )
+            }
+            _ if type_name.starts_with("git+") => {
+                let url_without_type = &base["git+".len()..];
+                let scheme = &type_name["git+".len()..];
+                check_url(url, scheme, path)?;
+                (
+                    FlakeRefKind::Git {
+                        url: url_without_type.to_string(),
+                    },
+                    None,
+                )
+            }
+            // the query of archives is part of the url they're downloaded from
+            _ if type_name.starts_with("tarball+") => {
+                check_url(url, &type_name["tarball+".len()..], path)?;
+                return Ok(Self::new(FlakeRefKind::Tarball {
+                    url: url.to_string(),
+                }));
+            }
+            "http" | "https" | "file" => {
+                check_url(url, type_name, path)?;
+                if !is_archive(url) {
+                    return Err(FlakeRefError::NotAnArchive(url.to_string()));
+                }
+                return Ok(Self::new(FlakeRefKind::Tarball {
+                    url: url.to_string(),
+                }));
+            }
+            _ => return Err(FlakeRefError::UnknownType(type_name.to_string())),
+        };
+
+        let mut flake_ref = Self::new(kind);
+        if let Some(path_ref) = path_ref {
+            let (git_ref, rev) = ref_or_rev(&path_ref);
+            flake_ref.git_ref = git_ref;
+            flake_ref.rev = rev;
+        }
+        flake_ref.parse_query(query)
+    }
+}
+
+/// checks the url after a `git+` or `tarball+` prefix has a host, or a path for files
+fn check_url(url: &str, scheme: &str, path: &str) -> Result<(), FlakeRefError> {
+    match scheme {
+        "file" if path.trim_start_matches('/').is_empty() => {
+            Err(FlakeRefError::MissingPath(url.to_string()))
+        }
+        "file" => Ok(()),
+        "http" | "https" | "ssh" => match path.strip_prefix("//") {
+            Some(rest) if !rest.starts_with('/') && !rest.is_empty() => Ok(()),
+            _ => Err(FlakeRefError::MissingHost(url.to_string())),
+        },
+        _ => Err(FlakeRefError::UnknownType(
+            url.split(':').next().unwrap_or_default().to_string(),
+        )),
+    }
+}
+
+impl FlakeRef {
+    fn parse_query(mut self, query: &str) -> Result<Self, FlakeRefError> {
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let mut parts = param.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if !name.is_empty() => self.set_param(name, value)?,
+                _ => return Err(FlakeRefError::InvalidParam(param.to_string())),
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// the normalized url: refs and revs go in the path when the type allows it,
+/// other parameters keep their order
+impl fmt::Display for FlakeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let url = match &self.kind {
+            FlakeRefKind::Forge { forge, owner, repo } => {
+                let base = format!("{}:{}/{}", forge, owner, repo);
+                match (&self.git_ref, &self.rev) {
+                    (Some(git_ref), None) => {
+                        self.with_params(format!("{}/{}", base, git_ref), true)
+                    }
+                    (None, Some(rev)) => self.with_params(format!("{}/{}", base, rev), true),
+                    _ => self.with_params(base, false),
+                }
+            }
+            FlakeRefKind::Git { url } => self.with_params(format!("git+{}", url), false),
+            FlakeRefKind::Path { path } => self.with_params(format!("path:{}", path), false),
+            FlakeRefKind::Tarball { url } => self.with_params(url.clone(), false),
+            FlakeRefKind::Indirect { id } => {
+                let mut base = id.clone();
+                for element in self.git_ref.iter().chain(self.rev.iter()) {
+                    base.push('/');
+                    base.push_str(element);
+                }
+                self.with_params(base, true)
+            }
+        };
+        write!(f, "{}", url)
+    }
+}
@@ -0,0 +1,201 @@
+use crate::parser::flake_ref::{FlakeRef, FlakeRefError, FlakeRefKind, Forge};
+
+use std::str::FromStr;
+
+const REV: &str = "4d8dd0afd2d5a35acb5e34c5c7b1674b74173d87";
+
+fn parse(url: &str) -> FlakeRef {
+    FlakeRef::from_str(url).unwrap()
+}
+
+fn attrs(attrs: &[(&str, &str)]) -> Vec<(String, String)> {
+    attrs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+pub fn parse_flake_refs() {
+    let nixpkgs = parse("github:NixOS/nixpkgs/nixpkgs-unstable?dir=lib");
+    assert_eq!(
+        nixpkgs.kind,
+        FlakeRefKind::Forge {
+            forge: Forge::GitHub,
+            owner: "NixOS".to_string(),
+            repo: "nixpkgs".to_string(),
+        }
+    );
+    assert_eq!(nixpkgs.git_ref.as_deref(), Some("nixpkgs-unstable"));
+    assert_eq!(nixpkgs.rev, None);
+    assert_eq!(nixpkgs.params, attrs(&[("dir", "lib")]));
+
+    let pinned = parse(&format!("sourcehut:~foo/bar/{}", REV));
+    assert_eq!(pinned.git_ref, None);
+    assert_eq!(pinned.rev.as_deref(), Some(REV));
+
+    let git = parse("git+ssh://git@example.org/repo.git?ref=main&submodules=1");
+    assert_eq!(
+        git.kind,
+        FlakeRefKind::Git {
+            url: "ssh://git@example.org/repo.git".to_string()
+        }
+    );
+    assert_eq!(git.git_ref.as_deref(), Some("main"));
+    assert_eq!(git.params, attrs(&[("submodules", "1")]));
+
+    assert_eq!(
+        parse("./sub").kind,
+        FlakeRefKind::Path {
+            path: "./sub".to_string()
+        }
+    );
+    assert_eq!(
+        parse("git+file:///src/repo").kind,
+        FlakeRefKind::Git {
+            url: "file:///src/repo".to_string()
+        }
+    );
+    // the query of an archive belongs to its url
+    assert_eq!(
+        parse("https://example.org/a.tar.gz?token=abc").kind,
+        FlakeRefKind::Tarball {
+            url: "https://example.org/a.tar.gz?token=abc".to_string()
+        }
+    );
+
+    let indirect = parse(&format!("flake:nixpkgs/nixos-21.05/{}", REV));
+    assert_eq!(
+        indirect.kind,
+        FlakeRefKind::Indirect {
+            id: "nixpkgs".to_string()
+        }
+    );
+    assert_eq!(indirect.git_ref.as_deref(), Some("nixos-21.05"));
+    assert_eq!(indirect.rev.as_deref(), Some(REV));
+}
+
+#[test]
+pub fn normalize_flake_refs() {
+    let normalized = |url: &str| parse(url).to_string();
+    assert_eq!(
+        normalized("github:NixOS/nixpkgs?ref=nixos-21.05"),
+        "github:NixOS/nixpkgs/nixos-21.05"
+    );
+    assert_eq!(
+        normalized(&format!("github:NixOS/nixpkgs?rev={}&dir=lib", REV)),
+        format!("github:NixOS/nixpkgs/{}?dir=lib", REV)
+    );
+    assert_eq!(normalized("flake:nixpkgs"), "nixpkgs");
+    assert_eq!(normalized("/src/repo"), "path:/src/repo");
+    assert_eq!(
+        normalized("git+https://example.org/repo?dir=sub&ref=main"),
+        "git+https://example.org/repo?dir=sub&ref=main"
+    );
+    assert_eq!(
+        normalized("tarball+https://example.org/archive"),
+        "tarball+https://example.org/archive"
+    );
+}
+
+#[test]
+pub fn invalid_flake_refs() {
+    let error = |url: &str| FlakeRef::from_str(url).unwrap_err();
+    assert_eq!(error(""), FlakeRefError::Empty);
+    assert_eq!(
+        error("github:NixOS"),
+        FlakeRefError::MissingOwnerOrRepo("github:NixOS".to_string())
+    );
+    assert_eq!(
+        error("github:NixOS//nixpkgs"),
+        FlakeRefError::MissingOwnerOrRepo("github:NixOS//nixpkgs".to_string())
+    );
+    assert_eq!(
+        error("gihtub:NixOS/nixpkgs"),
+        FlakeRefError::UnknownType("gihtub".to_string())
+    );
+    assert_eq!(
+        error("github:NixOS/nixpkgs?rev=abc"),
+        FlakeRefError::InvalidRev("abc".to_string())
+    );
+    assert_eq!(
+        error("github:NixOS/nixpkgs?dir"),
+        FlakeRefError::InvalidParam("dir".to_string())
+    );
+    assert_eq!(
+        error("github:NixOS/nixpkgs?submodules=1"),
+        FlakeRefError::UnsupportedParam("github".to_string(), "submodules".to_string())
+    );
+    assert_eq!(
+        error("github:NixOS/nixpkgs/master?ref=master"),
+        FlakeRefError::Duplicate("ref".to_string())
+    );
+    assert_eq!(
+        error(&format!("github:NixOS/nixpkgs?ref=master&rev={}", REV)),
+        FlakeRefError::RefAndRev("github".to_string())
+    );
+    assert_eq!(
+        error(&format!("gitlab:owner/repo/master?rev={}", REV)),
+        FlakeRefError::RefAndRev("gitlab".to_string())
+    );
+    assert_eq!(
+        error("git+https:/repo"),
+        FlakeRefError::MissingHost("git+https:/repo".to_string())
+    );
+    assert_eq!(
+        error("https://example.org/repo"),
+        FlakeRefError::NotAnArchive("https://example.org/repo".to_string())
+    );
+    assert_eq!(
+        error("1nixpkgs"),
+        FlakeRefError::InvalidId("1nixpkgs".to_string())
+    );
+    assert_eq!(
+        error("nixpkgs#hello"),
+        FlakeRefError::Fragment("hello".to_string())
+    );
+    assert_eq!(
+        error("nixpkgs/a/b/c"),
+        FlakeRefError::TooManyElements("nixpkgs/a/b/c".to_string())
+    );
+}
+
+#[test]
+pub fn flake_ref_attrs() {
+    let github = attrs(&[
+        ("type", "github"),
+        ("owner", "NixOS"),
+        ("repo", "nixpkgs"),
+        ("ref", "nixos-21.05"),
+        ("dir", "lib"),
+    ]);
+    let flake_ref = FlakeRef::from_attrs(&github).unwrap();
+    assert_eq!(flake_ref, parse("github:NixOS/nixpkgs/nixos-21.05?dir=lib"));
+    assert_eq!(flake_ref.to_attrs(), github);
+
+    let git = parse("git+https://example.org/repo.git?ref=main");
+    assert_eq!(
+        git.to_attrs(),
+        attrs(&[
+            ("type", "git"),
+            ("url", "https://example.org/repo.git"),
+            ("ref", "main")
+        ])
+    );
+    assert_eq!(FlakeRef::from_attrs(&git.to_attrs()).unwrap(), git);
+
+    assert_eq!(
+        FlakeRef::from_attrs(&attrs(&[("type", "github"), ("owner", "NixOS")])),
+        Err(FlakeRefError::MissingAttribute("repo".to_string()))
+    );
+    assert_eq!(
+        FlakeRef::from_attrs(&attrs(&[
+            ("type", "github"),
+            ("owner", "NixOS"),
+            ("repo", "nixpkgs"),
+            ("ref", "master"),
+            ("rev", REV),
+        ])),
+        Err(FlakeRefError::RefAndRev("github".to_string()))
+    );
+}
@@ -0,0 +1,568 @@
+use crate::parser::flake::{
+    pinned_url, Dep, DepAttr, DepList, Flake, Follows, InputStyle, InputsLayout, Pin,
+};
+use crate::parser::lock::FlakeLock;
+use crate::parser::utils::get_inputs;
+
+use std::path::Path;
+use std::str::FromStr;
+
+fn inputs_flake() -> Flake {
+    Flake::parse(include_str!("../../test_data/inputs.nix").to_string()).unwrap()
+}
+
+#[test]
+pub fn check_flake_inputs() {
+    let flake = inputs_flake();
+    let inputs = flake.inputs();
+    let names = inputs.iter().map(|input| input.name()).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec!["nixpkgs", "nixCargoIntegration", "hello", "another_one"]
+    );
+
+    let nixpkgs = flake.input("nixpkgs").unwrap();
+    assert_eq!(
+        nixpkgs.url().unwrap(),
+        "github:NixOS/nixpkgs/nixpkgs-unstable"
+    );
+    assert!(nixpkgs.is_flake());
+    assert!(nixpkgs.follows().is_empty());
+
+    let nix_cargo_integration = flake.input("nixCargoIntegration").unwrap();
+    assert_eq!(
+        nix_cargo_integration.url().unwrap(),
+        "github:yusdacra/nix-cargo-integration"
+    );
+    assert_eq!(
+        nix_cargo_integration.follows(),
+        vec![Follows {
+            input: "nixCargoIntegration".to_string(),
+            nested: "nixpkgs".to_string(),
+            target: "nixpkgs".to_string(),
+        }]
+    );
+
+    assert_eq!(
+        flake.input("another_one").unwrap().url().unwrap(),
+        "hello_world"
+    );
+    assert!(flake.input("missing").is_none());
+    assert_eq!(flake.description(), None);
+}
+
+#[test]
+pub fn check_flake_outputs() {
+    let flake = inputs_flake();
+    assert_eq!(flake.output_args(), Some(vec![]));
+    assert_eq!(flake.output_body().unwrap().to_string(), "{}");
+
+    let flake = Flake::parse(include_str!("../../test_data/multi_arg.nix").to_string()).unwrap();
+    assert_eq!(
+        flake.output_args(),
+        Some(vec!["hello".to_string(), "another_one".to_string()])
+    );
+
+    let flake = Flake::parse(include_str!("../../test_data/no_args.nix").to_string()).unwrap();
+    assert_eq!(flake.output_args(), None);
+}
+
+#[test]
+pub fn edit_through_flake() {
+    let flake = inputs_flake()
+        .add_input("src", "github:foo/bar", false)
+        .unwrap()
+        .set_input_url("hello", "github:foo/hello")
+        .unwrap()
+        .remove_input("another_one")
+        .unwrap()
+        .follow_everywhere("nixpkgs", None)
+        .unwrap();
+
+    let src = flake.input("src").unwrap();
+    assert!(!src.is_flake());
+    assert_eq!(src.url().unwrap(), "github:foo/bar");
+    assert_eq!(
+        flake.input("hello").unwrap().url().unwrap(),
+        "github:foo/hello"
+    );
+    assert!(flake.input("another_one").is_none());
+    assert_eq!(flake.output_args(), Some(vec!["src".to_string()]));
+
+    let follows = flake.follows();
+    assert_eq!(follows.len(), 2);
+    let hello_follows = Follows::from_str(&follows[1].to_string()).unwrap();
+    assert_eq!(
+        hello_follows.to_string(),
+        "hello.inputs.nixpkgs follows nixpkgs"
+    );
+
+    let flake = flake
+        .retarget_follows(&hello_follows, "nixCargoIntegration")
+        .unwrap();
+    assert_eq!(
+        flake.input("hello").unwrap().follows()[0].target,
+        "nixCargoIntegration"
+    );
+    let flake = flake.remove_follows(&hello_follows).unwrap();
+    assert!(flake.input("hello").unwrap().follows().is_empty());
+    assert!(flake.remove_input("missing").is_err());
+
+    let flake = flake
+        .add_follows(&Follows {
+            input: "src".to_string(),
+            nested: "nixpkgs".to_string(),
+            target: "nixpkgs".to_string(),
+        })
+        .unwrap()
+        .remove_input("src")
+        .unwrap();
+    assert!(flake.input("src").is_none());
+    assert!(!flake.to_string().contains("src"));
+    assert_eq!(flake.output_args(), Some(vec![]));
+}
+
+#[test]
+pub fn follow_everywhere_with_lock() {
+    let flake = inputs_flake();
+    let follows = flake.follows()[0].clone();
+    let flake = flake.remove_follows(&follows).unwrap();
+    let lock = FlakeLock::parse(include_str!("../../test_data/inputs.lock")).unwrap();
+
+    // only nixCargoIntegration is locked with a nixpkgs input of its own
+    let followed = flake.follow_everywhere("nixpkgs", Some(&lock)).unwrap();
+    assert_eq!(
+        followed
+            .follows()
+            .iter()
+            .map(Follows::to_string)
+            .collect::<Vec<_>>(),
+        vec!["nixCargoIntegration.inputs.nixpkgs follows nixpkgs"]
+    );
+    // without one, every input is assumed to have it
+    let followed = flake.follow_everywhere("nixpkgs", None).unwrap();
+    assert_eq!(followed.follows().len(), 3);
+}
+
+#[test]
+pub fn remove_unused_inputs() {
+    // nixpkgs is followed by nixCargoIntegration
+    let flake = inputs_flake();
+    assert_eq!(
+        flake.unused_inputs().unwrap(),
+        vec!["another_one", "hello", "nixCargoIntegration"]
+    );
+    let flake = flake.remove_unused_inputs().unwrap();
+    assert_eq!(flake.inputs().len(), 1);
+    // nothing follows nixpkgs anymore
+    assert_eq!(flake.unused_inputs().unwrap(), vec!["nixpkgs"]);
+
+    let flake = Flake::parse(include_str!("../../test_data/let_outputs.nix").to_string()).unwrap();
+    assert_eq!(flake.unused_inputs().unwrap(), vec!["unused"]);
+
+    let flake = Flake::parse(include_str!("../../test_data/ident_arg.nix").to_string())
+        .unwrap()
+        .add_input("extra", "github:foo/bar", true)
+        .unwrap();
+    assert_eq!(flake.unused_inputs().unwrap(), vec!["extra"]);
+
+    // any input may be used by outputs.nix
+    let flake = Flake::parse(
+        "{\n  inputs.hello.url = \"abc\";\n  outputs = inputs: import ./outputs.nix inputs;\n}"
+            .to_string(),
+    )
+    .unwrap();
+    assert!(flake.unused_inputs().unwrap().is_empty());
+}
+
+#[test]
+pub fn pin_urls() {
+    let rev = "4d8dd0afd2d5a35acb5e34c5c7b1674b74173d87";
+    let pin = Pin::Rev(rev.to_string());
+    let tag = Pin::Tag {
+        name: "v1.0".to_string(),
+        rev: rev.to_string(),
+    };
+    let dir = Path::new("/src/flake");
+    let pinned = |url: &str, pin: &Pin| pinned_url(url, pin, dir).unwrap();
+
+    assert_eq!(
+        pinned("github:NixOS/nixpkgs/nixpkgs-unstable", &pin),
+        format!("github:NixOS/nixpkgs/{}", rev)
+    );
+    assert_eq!(
+        pinned("gitlab:foo/bar?dir=sub", &tag),
+        "gitlab:foo/bar/v1.0?dir=sub"
+    );
+    assert_eq!(pinned("nixpkgs", &tag), "nixpkgs/v1.0");
+    assert_eq!(
+        pinned("git+https://example.org/repo.git?ref=main&dir=sub", &pin),
+        format!("git+https://example.org/repo.git?dir=sub&rev={}", rev)
+    );
+    assert_eq!(
+        pinned("git+file:///src/repo", &tag),
+        format!("git+file:///src/repo?ref=refs/tags/v1.0&rev={}", rev)
+    );
+    assert_eq!(
+        pinned("path:/src/repo", &pin),
+        format!("git+file:///src/repo?rev={}", rev)
+    );
+    // nix only takes absolute paths in `git+file:` urls
+    assert_eq!(
+        pinned("path:./checkout", &pin),
+        format!("git+file:///src/flake/checkout?rev={}", rev)
+    );
+    assert_eq!(
+        pinned("git+file:../repo", &tag),
+        format!(
+            "git+file:///src/flake/../repo?ref=refs/tags/v1.0&rev={}",
+            rev
+        )
+    );
+    assert!(pinned_url("path:./checkout", &pin, Path::new("")).is_err());
+    assert!(pinned_url("https://example.org/archive.tar.gz", &pin, dir).is_err());
+    assert!(pinned_url("github:NixOS", &pin, dir).is_err());
+}
+
+#[test]
+pub fn pin_input() {
+    let flake = inputs_flake();
+    let pin = Pin::Rev("4d8dd0afd2d5a35acb5e34c5c7b1674b74173d87".to_string());
+    let pinned = flake
+        .pin_input("nixCargoIntegration", &pin, Path::new("/src"))
+        .unwrap();
+    assert_eq!(
+        pinned.input("nixCargoIntegration").unwrap().url().unwrap(),
+        "github:yusdacra/nix-cargo-integration/4d8dd0afd2d5a35acb5e34c5c7b1674b74173d87"
+    );
+    // only the url changes
+    assert_eq!(
+        pinned.to_string(),
+        flake.to_string().replace(
+            "github:yusdacra/nix-cargo-integration",
+            "github:yusdacra/nix-cargo-integration/4d8dd0afd2d5a35acb5e34c5c7b1674b74173d87"
+        )
+    );
+    assert!(flake.pin_input("missing", &pin, Path::new("/src")).is_err());
+}
+
+#[test]
+pub fn attrs_inputs() {
+    let flake = Flake::parse(include_str!("../../test_data/attrs_inputs.nix").to_string()).unwrap();
+    let mut names = get_inputs(flake.root()).keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            ".inputs.nixCargoIntegration.url",
+            ".inputs.nixpkgs.type",
+            ".inputs.private.type"
+        ]
+    );
+
+    let nixpkgs = flake.input("nixpkgs").unwrap();
+    assert_eq!(nixpkgs.style(), Some(InputStyle::Attrs));
+    assert_eq!(nixpkgs.url().unwrap(), "github:NixOS/nixpkgs/nixos-21.05");
+    assert_eq!(
+        flake.input("private").unwrap().url().unwrap(),
+        "git+ssh://git@example.org/private.git?submodules=1"
+    );
+
+    // follows can be added to inputs written either way
+    let follows = Follows {
+        input: "private".to_string(),
+        nested: "nixpkgs".to_string(),
+        target: "nixpkgs".to_string(),
+    };
+    let flake = flake.add_follows(&follows).unwrap();
+    assert!(flake
+        .to_string()
+        .contains("private.type = \"git\";\n    private.inputs.nixpkgs.follows = \"nixpkgs\";"));
+}
+
+#[test]
+pub fn convert_inputs() {
+    let original = include_str!("../../test_data/attrs_inputs.nix");
+    let flake = Flake::parse(original.to_string()).unwrap();
+
+    let as_urls = flake.convert_inputs(InputStyle::Url).unwrap();
+    assert_eq!(
+        as_urls.to_string(),
+        r#"{
+  inputs = {
+    nixpkgs = {
+      url = "github:NixOS/nixpkgs/nixos-21.05";
+    };
+    nixCargoIntegration = {
+      # pinned until the next release
+      url = "github:yusdacra/nix-cargo-integration?dir=lib";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+    private.url = "git+ssh://git@example.org/private.git?submodules=1";
+    private.flake = false;
+  };
+
+  outputs = { self, nixpkgs, nixCargoIntegration, private }: {};
+}
+"#
+    );
+
+    let as_attrs = as_urls.convert_inputs(InputStyle::Attrs).unwrap();
+    assert_eq!(
+        as_attrs.to_string(),
+        r#"{
+  inputs = {
+    nixpkgs = {
+      type = "github";
+      owner = "NixOS";
+      repo = "nixpkgs";
+      ref = "nixos-21.05";
+    };
+    nixCargoIntegration = {
+      # pinned until the next release
+      type = "github";
+      owner = "yusdacra";
+      repo = "nix-cargo-integration";
+      dir = "lib";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+    private.type = "git";
+    private.url = "ssh://git@example.org/private.git";
+    private.submodules = true;
+    private.flake = false;
+  };
+
+  outputs = { self, nixpkgs, nixCargoIntegration, private }: {};
+}
+"#
+    );
+    // already written that way
+    assert_eq!(
+        flake.convert_input("nixpkgs", InputStyle::Attrs).unwrap(),
+        flake
+    );
+
+    // changing the url of an attribute set input keeps it one
+    let changed = flake
+        .set_input_url("nixpkgs", "github:NixOS/nixpkgs/nixos-unstable")
+        .unwrap();
+    assert!(changed.to_string().contains("ref = \"nixos-unstable\";"));
+    assert_eq!(
+        changed.input("nixpkgs").unwrap().style(),
+        Some(InputStyle::Attrs)
+    );
+}
+
+#[test]
+pub fn normalize_inputs() {
+    let nested = inputs_flake()
+        .normalize_inputs(InputsLayout::Nested)
+        .unwrap();
+    assert_eq!(
+        nested.to_string(),
+        r#"{
+  inputs = {
+    another_one.url = "hello_world";
+    hello.url = "abc";
+    nixCargoIntegration = {
+      url = "github:yusdacra/nix-cargo-integration";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+    nixpkgs.url = "github:NixOS/nixpkgs/nixpkgs-unstable";
+  };
+
+  outputs = inputs@{...}: {};
+}
+"#
+    );
+    assert_eq!(
+        nested
+            .normalize_inputs(InputsLayout::Nested)
+            .unwrap()
+            .to_string(),
+        nested.to_string()
+    );
+
+    let dotted = inputs_flake()
+        .normalize_inputs(InputsLayout::Dotted)
+        .unwrap();
+    assert_eq!(dotted.inputs().len(), 4);
+    assert_eq!(
+        dotted.to_string(),
+        r#"{
+  inputs.another_one.url = "hello_world";
+  inputs.hello.url = "abc";
+  inputs.nixCargoIntegration.url = "github:yusdacra/nix-cargo-integration";
+  inputs.nixCargoIntegration.inputs.nixpkgs.follows = "nixpkgs";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixpkgs-unstable";
+
+  outputs = inputs@{...}: {};
+}
+"#
+    );
+}
+
+#[test]
+pub fn normalize_inputs_keeps_comments() {
+    let flake = Flake::parse(
+        r#"{
+  # all the inputs
+  inputs = {
+    # the package set
+    nixpkgs.url = "github:NixOS/nixpkgs"; # unstable
+    flake-utils = {
+      url = "github:numtide/flake-utils";
+      # not a flake
+      flake = false;
+    };
+    # dangling at the end
+  };
+  outputs = { self, ... }: {};
+}"#
+        .to_string(),
+    )
+    .unwrap();
+    assert_eq!(
+        flake
+            .normalize_inputs(InputsLayout::Sets)
+            .unwrap()
+            .to_string(),
+        r#"{
+  # all the inputs
+  # dangling at the end
+  inputs = {
+    flake-utils = {
+      url = "github:numtide/flake-utils";
+      # not a flake
+      flake = false;
+    };
+    # the package set
+    nixpkgs = {
+      url = "github:NixOS/nixpkgs"; # unstable
+    };
+  };
+  outputs = { self, ... }: {};
+}"#
+    );
+}
+
+#[test]
+pub fn convert_input_moves_comments() {
+    let flake = Flake::parse(
+        r#"{
+  inputs.nixpkgs = {
+    type = "github";
+    # the owner
+    owner = "NixOS";
+    repo = "nixpkgs"; # the repo
+  };
+  outputs = { self, nixpkgs }: {};
+}"#
+        .to_string(),
+    )
+    .unwrap();
+    assert_eq!(
+        flake
+            .convert_input("nixpkgs", InputStyle::Url)
+            .unwrap()
+            .to_string(),
+        r#"{
+  inputs.nixpkgs = {
+    # the owner
+    # the repo
+    url = "github:NixOS/nixpkgs";
+  };
+  outputs = { self, nixpkgs }: {};
+}"#
+    );
+}
+
+fn build_inputs_flake() -> Flake {
+    Flake::parse(include_str!("../../test_data/build_inputs.nix").to_string()).unwrap()
+}
+
+#[test]
+pub fn list_deps() {
+    let flake = build_inputs_flake();
+    let lists = flake
+        .dep_lists()
+        .iter()
+        .map(DepList::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lists,
+        vec![
+            "packages.x86_64-linux.hello buildInputs",
+            "packages.x86_64-linux.hello nativeBuildInputs",
+            "devShell.x86_64-linux buildInputs",
+            "devShell.x86_64-linux nativeBuildInputs",
+            "devShell.x86_64-linux packages",
+        ]
+    );
+    let deps = flake.deps().iter().map(Dep::to_string).collect::<Vec<_>>();
+    assert_eq!(
+        deps,
+        vec![
+            "packages.x86_64-linux.hello buildInputs pkgs.openssl",
+            "packages.x86_64-linux.hello buildInputs pkgs.zlib",
+            "devShell.x86_64-linux nativeBuildInputs cargo",
+            "devShell.x86_64-linux nativeBuildInputs rustc",
+        ]
+    );
+    assert_eq!(Dep::from_str(&deps[0]).unwrap(), flake.deps()[0]);
+}
+
+#[test]
+pub fn add_and_remove_deps() {
+    let flake = build_inputs_flake();
+    let hello = |attr| DepList {
+        derivation: "packages.x86_64-linux.hello".to_string(),
+        attr,
+    };
+    let shell = |attr| DepList {
+        derivation: "devShell.x86_64-linux".to_string(),
+        attr,
+    };
+    let edited = flake
+        .add_dep(&hello(DepAttr::BuildInputs), "sqlite")
+        .and_then(|flake| flake.add_dep(&shell(DepAttr::NativeBuildInputs), "pkg-config"))
+        .and_then(|flake| flake.add_dep(&shell(DepAttr::Packages), "gdb"))
+        .and_then(|flake| {
+            flake.remove_dep(&Dep {
+                derivation: "packages.x86_64-linux.hello".to_string(),
+                attr: DepAttr::BuildInputs,
+                name: "pkgs.openssl".to_string(),
+            })
+        })
+        .unwrap();
+    assert_eq!(
+        edited.to_string(),
+        r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+  outputs = { self, nixpkgs }:
+    let
+      pkgs = nixpkgs.legacyPackages.x86_64-linux;
+    in
+    {
+      packages.x86_64-linux.hello = pkgs.stdenv.mkDerivation {
+        name = "hello";
+        buildInputs = [
+          pkgs.zlib
+          pkgs.sqlite
+        ];
+      };
+      devShell.x86_64-linux = pkgs.mkShell {
+        nativeBuildInputs = with pkgs; [ cargo rustc pkg-config ];
+        packages = [ pkgs.gdb ];
+      };
+    };
+}
+"#
+    );
+
+    assert!(flake.add_dep(&hello(DepAttr::BuildInputs), "zlib").is_err());
+    assert!(flake.add_dep(&hello(DepAttr::Packages), "gdb").is_err());
+    assert!(flake
+        .add_dep(&shell(DepAttr::Packages), "not a package")
+        .is_err());
+}
@@ -0,0 +1,414 @@
+use crate::parser::utils::{string_to_node, NixNode, NixToken};
+use rnix::{types::*, SyntaxKind::*};
+use rowan::{TextRange, TextSize};
+
+/// lists, attribute sets and patterns that would make a line longer than this
+/// are split over several lines
+const MAX_WIDTH: usize = 80;
+/// indentation of each level of nesting
+const INDENT: usize = 2;
+
+/// formats the whole file `root` belongs to, the way nixpkgs-fmt would
+/// returns the root of the newly created tree
+pub fn format(root: &NixNode) -> NixNode {
+    format_range(root, None)
+}
+
+/// formats the whitespace touching `range` in the file `root` belongs to,
+/// or the whole file if `range` is `None`
+/// only whitespace ever changes, if anything else would the file is left as it is
+/// returns the root of the newly created tree
+pub fn format_range(root: &NixNode, range: Option<TextRange>) -> NixNode {
+    let file = root.ancestors().last().unwrap_or_else(|| root.clone());
+    let formatted = Formatter::new(&file, range).run();
+    match string_to_node(formatted) {
+        Ok(new_root) if same_tokens(&file, &new_root) => new_root,
+        _ => root.clone(),
+    }
+}
+
+/// the lines of `new` that differ from `old`, `None` if they are the same
+pub fn changed_lines(old: &str, new: &str) -> Option<TextRange> {
+    if old == new {
+        return None;
+    }
+    let mut prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(old, new)| old == new)
+        .count();
+    while !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take_while(|(old, new)| old == new)
+        .count()
+        .min(old.len().min(new.len()) - prefix);
+    while !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
+    let start = new[..prefix].rfind('\n').map_or(0, |idx| idx + 1);
+    let end = new.len() - suffix;
+    let end = new[end..].find('\n').map_or(new.len(), |idx| end + idx);
+    Some(TextRange::new(
+        TextSize::from(start as u32),
+        TextSize::from(end as u32),
+    ))
+}
+
+/// whether both trees hold the same tokens, whitespace aside
+fn same_tokens(old: &NixNode, new: &NixNode) -> bool {
+    let tokens = |node: &NixNode| {
+        node.ancestors()
+            .last()
+            .unwrap_or_else(|| node.clone())
+            .descendants_with_tokens()
+            .filter_map(|element| element.into_token())
+            .filter(|tok| tok.kind() != TOKEN_WHITESPACE)
+            .map(|tok| (tok.kind(), tok.text().to_string()))
+            .collect::<Vec<_>>()
+    };
+    tokens(old) == tokens(new)
+}
+
+/// what the layout asks of the whitespace between two tokens
+enum Layout {
+    /// a new line
+    Break,
+    /// the same line
+    Join,
+    /// whatever was written
+    Keep,
+}
+
+struct Formatter {
+    source: String,
+    /// every token but whitespace, in order
+    tokens: Vec<NixToken>,
+    range: Option<TextRange>,
+    out: String,
+    /// indentation of the line each token was written on
+    line_indents: Vec<usize>,
+    /// column each token was written at
+    columns: Vec<usize>,
+    /// whether lists, attribute sets, let bindings and patterns are split over several lines
+    split: Vec<(NixNode, bool)>,
+}
+
+impl Formatter {
+    fn new(file: &NixNode, range: Option<TextRange>) -> Self {
+        Self {
+            source: file.to_string(),
+            tokens: file
+                .descendants_with_tokens()
+                .filter_map(|element| element.into_token())
+                .filter(|tok| tok.kind() != TOKEN_WHITESPACE)
+                .collect(),
+            range,
+            out: String::new(),
+            line_indents: Vec::new(),
+            columns: Vec::new(),
+            split: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> String {
+        let mut previous: Option<NixToken> = None;
+        for token in self.tokens.clone() {
+            let start = token.text_range().start();
+            let gap_start = previous
+                .as_ref()
+                .map_or_else(|| TextSize::from(0), |prev| prev.text_range().end());
+            let original = self.source[TextRange::new(gap_start, start)].to_string();
+            let gap = match &previous {
+                Some(prev) if self.touches(gap_start, start) && !in_literal(prev, &token) => {
+                    self.gap(prev, &token, &original)
+                }
+                // nothing goes before the first token
+                None if self.touches(gap_start, start) => String::new(),
+                _ => original,
+            };
+            self.out.push_str(&gap);
+            self.columns.push(current_column(&self.out));
+            self.line_indents.push(current_indent(&self.out));
+            self.out.push_str(token.text());
+            previous = Some(token);
+        }
+        let end = previous.map_or_else(|| TextSize::from(0), |prev| prev.text_range().end());
+        let end_of_file = TextSize::of(self.source.as_str());
+        if self.touches(end, end_of_file) && !self.tokens.is_empty() {
+            self.out.push('\n');
+        } else {
+            self.out
+                .push_str(&self.source[TextRange::new(end, end_of_file)]);
+        }
+        self.out
+    }
+
+    /// whether the whitespace between `start` and `end` is to be formatted
+    fn touches(&self, start: TextSize, end: TextSize) -> bool {
+        self.range
+            .map_or(true, |range| range.start() <= end && start <= range.end())
+    }
+
+    /// the whitespace to write between `prev` and `token`, `original` being what was written
+    fn gap(&mut self, prev: &NixToken, token: &NixToken, original: &str) -> String {
+        let mut newlines = original.matches('\n').count().min(2);
+        if let Some(owner) = common_ancestor(prev, token) {
+            match self.layout(&owner, prev, token) {
+                Layout::Break => newlines = newlines.max(1),
+                Layout::Join => newlines = 0,
+                Layout::Keep => {}
+            }
+        }
+        // no blank line right inside brackets
+        if opens(prev) || closes(token) {
+            newlines = newlines.min(1);
+        }
+        // a comment written after something stays there
+        if token.kind() == TOKEN_COMMENT && !original.contains('\n') {
+            newlines = 0;
+        }
+        if is_line_comment(prev) {
+            newlines = newlines.max(1);
+        }
+        if newlines == 0 {
+            spacing(prev, token).to_string()
+        } else {
+            format!(
+                "{}{}",
+                "\n".repeat(newlines),
+                " ".repeat(self.indent(token))
+            )
+        }
+    }
+
+    fn layout(&mut self, owner: &NixNode, prev: &NixToken, token: &NixToken) -> Layout {
+        if token.kind() == TOKEN_SEMICOLON {
+            return Layout::Join;
+        }
+        match owner.kind() {
+            NODE_ATTR_SET if prev.kind() != TOKEN_REC && self.is_split(owner) => Layout::Break,
+            NODE_LIST if self.is_split(owner) => Layout::Break,
+            NODE_LET_IN if prev.kind() != TOKEN_IN && self.is_split(owner) => Layout::Break,
+            // { lib
+            // , stdenv
+            // }:
+            NODE_PATTERN if self.is_split(owner) => match token.kind() {
+                TOKEN_COMMA | TOKEN_CURLY_B_CLOSE => Layout::Break,
+                _ => Layout::Join,
+            },
+            // and so does its body
+            NODE_LAMBDA if prev.kind() == TOKEN_COLON => {
+                match Lambda::cast(owner.clone()).and_then(|lambda| lambda.arg()) {
+                    Some(arg) if arg.kind() == NODE_PATTERN && self.is_split(&arg) => Layout::Break,
+                    _ => Layout::Keep,
+                }
+            }
+            // a function with a split pattern starts on its own line
+            NODE_KEY_VALUE if prev.kind() == TOKEN_ASSIGN => {
+                let pattern = KeyValue::cast(owner.clone())
+                    .and_then(|entry| entry.value())
+                    .filter(|value| value.first_token().as_ref() == Some(token))
+                    .and_then(Lambda::cast)
+                    .and_then(|lambda| lambda.arg())
+                    .filter(|arg| arg.kind() == NODE_PATTERN);
+                match pattern {
+                    Some(pattern) if self.is_split(&pattern) => Layout::Break,
+                    _ => Layout::Keep,
+                }
+            }
+            _ => Layout::Keep,
+        }
+    }
+
+    /// whether `node` is split over several lines: it already is, or it wouldn't fit on one
+    fn is_split(&mut self, node: &NixNode) -> bool {
+        if let Some((_, split)) = self.split.iter().find(|(split, _)| split == node) {
+            return *split;
+        }
+        let split =
+            has_line_break(node) || self.start_column(node) + single_line_width(node) > MAX_WIDTH;
+        self.split.push((node.clone(), split));
+        split
+    }
+
+    fn start_column(&self, node: &NixNode) -> usize {
+        match node.first_token().and_then(|tok| self.index_of(&tok)) {
+            Some(idx) if idx < self.columns.len() => self.columns[idx],
+            // not written yet, it goes after what's on the current line
+            _ => current_column(&self.out) + 1,
+        }
+    }
+
+    fn index_of(&self, token: &NixToken) -> Option<usize> {
+        self.tokens
+            .binary_search_by_key(&token.text_range().start(), |tok| tok.text_range().start())
+            .ok()
+    }
+
+    /// indentation of the line a token was written on
+    fn line_indent_of(&self, token: &NixToken) -> usize {
+        self.index_of(token)
+            .and_then(|idx| self.line_indents.get(idx))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// indentation of `token` when it starts a line: one level more than the line
+    /// the construct holding it starts on, closing brackets excepted
+    fn indent(&self, token: &NixToken) -> usize {
+        let start = token.text_range().start();
+        let starts_at = |node: Option<NixNode>| node.map(|node| node.text_range().start());
+        for node in token.ancestors() {
+            let anchor = match node.first_token() {
+                Some(anchor) if anchor != *token => anchor,
+                // the construct starts with the token, look further out
+                _ => continue,
+            };
+            let base = self.line_indent_of(&anchor);
+            let is_child = token.parent() == node;
+            match node.kind() {
+                NODE_ATTR_SET | NODE_LIST | NODE_PAREN => {
+                    return if is_child && closes(token) {
+                        base
+                    } else {
+                        base + INDENT
+                    };
+                }
+                NODE_LET_IN => {
+                    let body =
+                        starts_at(LetIn::cast(node.clone()).and_then(|let_in| let_in.body()));
+                    if body.map_or(false, |body| start >= body) {
+                        continue;
+                    }
+                    return if token.kind() == TOKEN_IN {
+                        base
+                    } else {
+                        base + INDENT
+                    };
+                }
+                NODE_PATTERN => {
+                    return if is_child && matches!(token.kind(), TOKEN_COMMA | TOKEN_CURLY_B_CLOSE)
+                    {
+                        base
+                    } else {
+                        base + INDENT
+                    };
+                }
+                NODE_IF_ELSE => {
+                    return if matches!(token.kind(), TOKEN_THEN | TOKEN_ELSE) {
+                        base
+                    } else {
+                        base + INDENT
+                    };
+                }
+                NODE_INHERIT => return base + INDENT,
+                NODE_KEY_VALUE | NODE_APPLY | NODE_BIN_OP => {
+                    // the value, the argument or what follows the operator
+                    let continues = match node.kind() {
+                        NODE_KEY_VALUE => {
+                            starts_at(KeyValue::cast(node.clone()).and_then(|entry| entry.value()))
+                        }
+                        NODE_APPLY => {
+                            starts_at(Apply::cast(node.clone()).and_then(|apply| apply.value()))
+                        }
+                        _ => BinOp::cast(node.clone())
+                            .and_then(|op| op.lhs())
+                            .map(|lhs| lhs.text_range().end()),
+                    };
+                    if continues.map_or(false, |continues| start >= continues) {
+                        return base + INDENT;
+                    }
+                }
+                NODE_ROOT => return 0,
+                _ => {}
+            }
+        }
+        0
+    }
+}
+
+fn common_ancestor(a: &NixToken, b: &NixToken) -> Option<NixNode> {
+    let ancestors = a.ancestors().collect::<Vec<_>>();
+    b.ancestors().find(|node| ancestors.contains(node))
+}
+
+/// whether the whitespace between two tokens is part of a string or path
+fn in_literal(a: &NixToken, b: &NixToken) -> bool {
+    common_ancestor(a, b).map_or(false, |owner| {
+        owner
+            .ancestors()
+            .any(|node| matches!(node.kind(), NODE_STRING | NODE_PATH_WITH_INTERPOL))
+    })
+}
+
+fn opens(token: &NixToken) -> bool {
+    matches!(
+        token.kind(),
+        TOKEN_CURLY_B_OPEN | TOKEN_SQUARE_B_OPEN | TOKEN_PAREN_OPEN | TOKEN_LET
+    )
+}
+
+fn closes(token: &NixToken) -> bool {
+    matches!(
+        token.kind(),
+        TOKEN_CURLY_B_CLOSE | TOKEN_SQUARE_B_CLOSE | TOKEN_PAREN_CLOSE | TOKEN_IN
+    )
+}
+
+fn is_line_comment(token: &NixToken) -> bool {
+    token.kind() == TOKEN_COMMENT && token.text().starts_with('#')
+}
+
+/// whether `node` is written over several lines, or has to be
+fn has_line_break(node: &NixNode) -> bool {
+    node.descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .any(|tok| {
+            (tok.kind() == TOKEN_WHITESPACE && tok.text().contains('\n')) || is_line_comment(&tok)
+        })
+}
+
+/// width of `node` written on a single line
+fn single_line_width(node: &NixNode) -> usize {
+    let tokens = node
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .filter(|tok| tok.kind() != TOKEN_WHITESPACE)
+        .collect::<Vec<_>>();
+    tokens.iter().map(|tok| tok.text().len()).sum::<usize>()
+        + tokens
+            .windows(2)
+            .map(|pair| spacing(&pair[0], &pair[1]).len())
+            .sum::<usize>()
+}
+
+/// the space between two tokens on the same line
+fn spacing(prev: &NixToken, token: &NixToken) -> &'static str {
+    match (prev.kind(), token.kind()) {
+        (_, TOKEN_COMMENT) => " ",
+        (_, TOKEN_SEMICOLON) | (_, TOKEN_COMMA) | (_, TOKEN_COLON) => "",
+        (TOKEN_PAREN_OPEN, _) | (_, TOKEN_PAREN_CLOSE) => "",
+        (TOKEN_AT, _) | (_, TOKEN_AT) | (TOKEN_DOT, _) | (_, TOKEN_DOT) => "",
+        (TOKEN_DYNAMIC_START, _) | (_, TOKEN_DYNAMIC_END) => "",
+        (TOKEN_SUB, _) | (TOKEN_INVERT, _) if prev.parent().kind() == NODE_UNARY_OP => "",
+        _ => " ",
+    }
+}
+
+fn current_column(out: &str) -> usize {
+    out.rsplit('\n').next().unwrap_or_default().chars().count()
+}
+
+fn current_indent(out: &str) -> usize {
+    out.rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .count()
+}
@@ -0,0 +1,100 @@
+use crate::parser::flake::Flake;
+use crate::parser::format::changed_lines;
+
+use rowan::{TextRange, TextSize};
+
+fn formatted(content: &str) -> String {
+    Flake::parse(content.to_string())
+        .unwrap()
+        .format()
+        .to_string()
+}
+
+#[test]
+pub fn format_layout() {
+    assert_eq!(
+        formatted(
+            r#"# comment
+{
+description="messy" ;
+   inputs = { nixpkgs.url = "github:NixOS/nixpkgs";   # pinned
+  utils={url="github:numtide/flake-utils";flake=false;};
+
+
+  };
+outputs = { self, nixpkgs, ... }: let pkgs = import nixpkgs {};
+  in { devShell = pkgs.mkShell { buildInputs = [ pkgs.cargo
+  pkgs.rustc ]; }; list = [1 2 3]; };
+}"#
+        ),
+        r#"# comment
+{
+  description = "messy";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs"; # pinned
+    utils = { url = "github:numtide/flake-utils"; flake = false; };
+  };
+  outputs = { self, nixpkgs, ... }: let
+    pkgs = import nixpkgs { };
+  in {
+    devShell = pkgs.mkShell {
+      buildInputs = [
+        pkgs.cargo
+        pkgs.rustc
+      ];
+    };
+    list = [ 1 2 3 ];
+  };
+}
+"#
+    );
+}
+
+#[test]
+pub fn format_long_patterns() {
+    assert_eq!(
+        formatted(
+            "{ outputs = { self, nixpkgs, nixCargoIntegration, flake-utils, devshell, ... }@inputs: { }; }"
+        ),
+        r#"{
+  outputs =
+    { self
+    , nixpkgs
+    , nixCargoIntegration
+    , flake-utils
+    , devshell
+    , ...
+    }@inputs:
+    { };
+}
+"#
+    );
+}
+
+#[test]
+pub fn format_is_idempotent() {
+    for fixture in &[
+        include_str!("../../test_data/comments.nix"),
+        include_str!("../../test_data/let_outputs.nix"),
+        include_str!("../../test_data/attrs_inputs.nix"),
+    ] {
+        let once = formatted(fixture);
+        assert_eq!(formatted(&once), once);
+    }
+}
+
+#[test]
+pub fn format_changes_only() {
+    let original = "{\n  a   =   1;\n  b = {x=1;};\n}\n";
+    let edited = "{\n  a   =   1;\n  b = {x=2;};\n}\n";
+    assert_eq!(
+        changed_lines(original, edited),
+        Some(TextRange::new(TextSize::from(15), TextSize::from(28)))
+    );
+    assert_eq!(changed_lines(original, original), None);
+    let flake = Flake::parse(edited.to_string()).unwrap();
+    assert_eq!(
+        flake.format_changes(original).to_string(),
+        "{\n  a   =   1;\n  b = { x = 2; };\n}\n"
+    );
+}
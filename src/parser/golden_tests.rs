@@ -0,0 +1,101 @@
+use crate::parser::flake::Flake;
+
+/// parses `fixture`, checks it is written back unchanged, then applies `edit`
+/// and compares the result byte for byte with `golden`
+fn check_golden(fixture: &str, golden: &str, edit: impl FnOnce(&Flake) -> anyhow::Result<Flake>) {
+    let flake = Flake::parse(fixture.to_string()).unwrap();
+    assert_eq!(flake.to_string(), fixture);
+    assert_eq!(edit(&flake).unwrap().to_string(), golden);
+}
+
+#[test]
+pub fn golden_attrs_inputs() {
+    // removes an input written over several lines, comment included
+    check_golden(
+        include_str!("../../test_data/attrs_inputs.nix"),
+        include_str!("../../test_data/golden/attrs_inputs.nix"),
+        |flake| flake.remove_input("nixCargoIntegration"),
+    );
+}
+
+#[test]
+pub fn golden_comments() {
+    // keeps the blank line between entries
+    check_golden(
+        include_str!("../../test_data/comments.nix"),
+        include_str!("../../test_data/golden/comments.nix"),
+        |flake| flake.remove_input("utils"),
+    );
+}
+
+#[test]
+pub fn golden_ident_arg() {
+    check_golden(
+        include_str!("../../test_data/ident_arg.nix"),
+        include_str!("../../test_data/golden/ident_arg.nix"),
+        |flake| flake.rename_input("another_one", "other"),
+    );
+}
+
+#[test]
+pub fn golden_inputs() {
+    check_golden(
+        include_str!("../../test_data/inputs.nix"),
+        include_str!("../../test_data/golden/inputs.nix"),
+        |flake| flake.remove_input("hello"),
+    );
+}
+
+#[test]
+pub fn golden_let_outputs() {
+    check_golden(
+        include_str!("../../test_data/let_outputs.nix"),
+        include_str!("../../test_data/golden/let_outputs.nix"),
+        |flake| flake.remove_input("unused"),
+    );
+}
+
+#[test]
+pub fn golden_multi_arg() {
+    check_golden(
+        include_str!("../../test_data/multi_arg.nix"),
+        include_str!("../../test_data/golden/multi_arg.nix"),
+        |flake| flake.remove_input("hello"),
+    );
+}
+
+#[test]
+pub fn golden_no_args() {
+    check_golden(
+        include_str!("../../test_data/no_args.nix"),
+        include_str!("../../test_data/golden/no_args.nix"),
+        |flake| flake.add_input("nixpkgs", "github:NixOS/nixpkgs", true),
+    );
+}
+
+#[test]
+pub fn golden_one_arg_ellipsis() {
+    check_golden(
+        include_str!("../../test_data/one_arg_ellipsis.nix"),
+        include_str!("../../test_data/golden/one_arg_ellipsis.nix"),
+        |flake| flake.remove_input("hello"),
+    );
+}
+
+#[test]
+pub fn golden_one_arg_no_ellipsis() {
+    check_golden(
+        include_str!("../../test_data/one_arg_no_ellipsis.nix"),
+        include_str!("../../test_data/golden/one_arg_no_ellipsis.nix"),
+        |flake| flake.remove_input("hello"),
+    );
+}
+
+#[test]
+pub fn golden_zero_args() {
+    check_golden(
+        include_str!("../../test_data/zero_args.nix"),
+        include_str!("../../test_data/golden/zero_args.nix"),
+        |flake| flake.add_input("nixpkgs", "github:NixOS/nixpkgs", true),
+    );
+}
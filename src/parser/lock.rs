@@ -0,0 +1,378 @@
+use crate::parser::flake::Flake;
+
+use anyhow::{anyhow, bail};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// lock file versions we know how to read
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<u64> = 5..=7;
+/// how many follows `FlakeLock::resolve` goes through before giving up,
+/// since a lock file could have follows going in circles
+const MAX_FOLLOWS_DEPTH: usize = 32;
+
+/// Contents of a flake.lock
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlakeLock {
+    version: u64,
+    root: String,
+    nodes: Map<String, Value>,
+}
+
+/// The locked version of an input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedInput {
+    /// name of the node in the lock file, which may differ from the input's name
+    pub node: String,
+    pub rev: Option<String>,
+    pub nar_hash: Option<String>,
+    pub last_modified: Option<u64>,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    /// e.g. `["nixpkgs"]` if the input follows nixpkgs
+    pub follows: Option<Vec<String>>,
+}
+
+/// An input, as seen from both flake.nix and flake.lock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputStatus {
+    pub name: String,
+    pub locked: Option<LockedInput>,
+    pub in_flake: bool,
+}
+
+/// path of the lock file belonging to the flake at `filename`
+pub fn lock_filename(filename: &str) -> String {
+    Path::new(filename)
+        .with_file_name("flake.lock")
+        .to_string_lossy()
+        .to_string()
+}
+
+impl FlakeLock {
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let json: Value = serde_json::from_str(content)
+            .map_err(|err| anyhow!("could not parse the lock file: {}", err))?;
+        let version = json
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("the lock file has no version"))?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            bail!("unsupported lock file version {}", version);
+        }
+        let root = json
+            .get("root")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("the lock file has no root"))?
+            .to_string();
+        let nodes = json
+            .get("nodes")
+            .and_then(Value::as_object)
+            .ok_or_else(|| anyhow!("the lock file has no nodes"))?
+            .clone();
+        if !nodes.contains_key(&root) {
+            bail!("the root node {} of the lock file is missing", root);
+        }
+        Ok(Self {
+            version,
+            root,
+            nodes,
+        })
+    }
+
+    /// reads the lock file next to the flake at `filename`
+    /// returns `None` if there isn't any
+    pub fn read_for(filename: &str) -> anyhow::Result<Option<Self>> {
+        let lock_filename = lock_filename(filename);
+        match fs::read_to_string(&lock_filename) {
+            Ok(content) => Self::parse(&content).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => bail!("could not read {}: {}", lock_filename, err),
+        }
+    }
+
+    /// the lock file as nix writes it: sorted keys, indented with two spaces
+    pub fn to_json(&self) -> String {
+        let json = serde_json::json!({
+            "nodes": self.nodes,
+            "root": self.root,
+            "version": self.version,
+        });
+        // serializing a `Value` can't fail
+        let mut content = serde_json::to_string_pretty(&json).unwrap_or_default();
+        content.push('\n');
+        content
+    }
+
+    fn root_inputs_mut(&mut self) -> Option<&mut Map<String, Value>> {
+        self.nodes
+            .get_mut(&self.root)?
+            .get_mut("inputs")?
+            .as_object_mut()
+    }
+
+    /// drops an input of the flake, along with every node only it needed
+    /// follows going through the input point to the node they were locked to instead
+    pub fn remove_input(&self, name: &str) -> Self {
+        let mut dangling = Vec::new();
+        for (node, value) in &self.nodes {
+            let inputs = match value.get("inputs").and_then(Value::as_object) {
+                Some(inputs) => inputs,
+                None => continue,
+            };
+            for (input, target) in inputs {
+                let first = target
+                    .as_array()
+                    .and_then(|path| path.first())
+                    .and_then(Value::as_str);
+                if first == Some(name) {
+                    dangling.push((node.clone(), input.clone(), self.resolve(node, input, 0)));
+                }
+            }
+        }
+        let mut lock = self.clone();
+        for (node, input, target) in dangling {
+            let inputs = lock
+                .nodes
+                .get_mut(&node)
+                .and_then(|node| node.get_mut("inputs"))
+                .and_then(Value::as_object_mut);
+            if let Some(inputs) = inputs {
+                match target {
+                    Some(target) => inputs.insert(input, Value::String(target)),
+                    None => inputs.remove(&input),
+                };
+            }
+        }
+        if let Some(inputs) = lock.root_inputs_mut() {
+            inputs.remove(name);
+        }
+        lock.prune()
+    }
+
+    /// renames an input of the flake, and the follows going through it
+    /// the names of the nodes don't matter to nix, so they are left alone
+    pub fn rename_input(&self, old_name: &str, new_name: &str) -> Self {
+        let mut lock = self.clone();
+        if let Some(inputs) = lock.root_inputs_mut() {
+            if let Some(target) = inputs.remove(old_name) {
+                inputs.insert(new_name.to_string(), target);
+            }
+        }
+        // follows paths start at the root, so only their first element names an input of the flake
+        for node in lock.nodes.values_mut() {
+            let inputs = match node.get_mut("inputs").and_then(Value::as_object_mut) {
+                Some(inputs) => inputs,
+                None => continue,
+            };
+            for target in inputs.values_mut() {
+                if let Some(first) = target.as_array_mut().and_then(|path| path.first_mut()) {
+                    if first.as_str() == Some(old_name) {
+                        *first = Value::String(new_name.to_string());
+                    }
+                }
+            }
+        }
+        lock
+    }
+
+    /// removes the nodes that can't be reached from the root anymore
+    pub fn prune(&self) -> Self {
+        let mut reachable = vec![self.root.clone()];
+        let mut idx = 0;
+        while let Some(node) = reachable.get(idx).cloned() {
+            // follows only point to nodes that can be reached another way
+            let targets = self
+                .node_inputs(&node)
+                .map(|inputs| {
+                    inputs
+                        .values()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            for target in targets {
+                if !reachable.contains(&target) {
+                    reachable.push(target);
+                }
+            }
+            idx += 1;
+        }
+        let mut lock = self.clone();
+        lock.nodes.retain(|name, _| reachable.contains(name));
+        lock
+    }
+
+    /// the inputs of a node, as the json objects mapping input names
+    /// to a node name or a follows path
+    fn node_inputs(&self, node: &str) -> Option<&Map<String, Value>> {
+        self.nodes.get(node)?.get("inputs")?.as_object()
+    }
+
+    /// names of the inputs of the flake itself
+    pub fn input_names(&self) -> Vec<String> {
+        self.node_inputs(&self.root)
+            .map(|inputs| inputs.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// names of the inputs of the flake that have an input called `nested` themselves
+    pub fn inputs_with(&self, nested: &str) -> Vec<String> {
+        self.input_names()
+            .into_iter()
+            .filter(|name| {
+                self.resolve(&self.root, name, 0)
+                    .and_then(|node| self.node_inputs(&node))
+                    .map_or(false, |inputs| inputs.contains_key(nested))
+            })
+            .collect()
+    }
+
+    /// name of the node an input of `node` is locked to, going through follows
+    fn resolve(&self, node: &str, input: &str, depth: usize) -> Option<String> {
+        if depth > MAX_FOLLOWS_DEPTH {
+            return None;
+        }
+        match self.node_inputs(node)?.get(input)? {
+            Value::String(name) => Some(name.clone()),
+            // follows paths start at the root
+            Value::Array(path) => {
+                let mut current = self.root.clone();
+                for element in path {
+                    current = self.resolve(&current, element.as_str()?, depth + 1)?;
+                }
+                Some(current)
+            }
+            _ => None,
+        }
+    }
+
+    /// the locked version of one of the flake's inputs
+    pub fn input(&self, name: &str) -> Option<LockedInput> {
+        let node = self.resolve(&self.root, name, 0)?;
+        let locked = self.nodes.get(&node)?.get("locked");
+        let field = |key: &str| {
+            locked
+                .and_then(|locked| locked.get(key))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        let follows = self
+            .node_inputs(&self.root)
+            .and_then(|inputs| inputs.get(name))
+            .and_then(Value::as_array)
+            .map(|path| {
+                path.iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            });
+        Some(LockedInput {
+            rev: field("rev"),
+            nar_hash: field("narHash"),
+            last_modified: locked
+                .and_then(|locked| locked.get("lastModified"))
+                .and_then(Value::as_u64),
+            owner: field("owner"),
+            repo: field("repo"),
+            follows,
+            node,
+        })
+    }
+
+    /// every input of the flake and of the lock file, in the order of the flake,
+    /// followed by the inputs only found in the lock file
+    pub fn statuses(&self, flake: &Flake) -> Vec<InputStatus> {
+        let flake_inputs = flake
+            .inputs()
+            .iter()
+            .map(|input| input.name().to_string())
+            .collect::<Vec<_>>();
+        let mut statuses = flake_inputs
+            .iter()
+            .map(|name| InputStatus {
+                name: name.clone(),
+                locked: self.input(name),
+                in_flake: true,
+            })
+            .collect::<Vec<_>>();
+        statuses.extend(
+            self.input_names()
+                .into_iter()
+                .filter(|name| !flake_inputs.contains(name))
+                .map(|name| InputStatus {
+                    locked: self.input(&name),
+                    name,
+                    in_flake: false,
+                }),
+        );
+        statuses
+    }
+}
+
+impl LockedInput {
+    /// first characters of the locked revision, as git shows them
+    pub fn short_rev(&self) -> Option<&str> {
+        self.rev.as_deref().map(|rev| &rev[..rev.len().min(7)])
+    }
+
+    /// date of the locked revision, as `YYYY-MM-DD`
+    pub fn date(&self) -> Option<String> {
+        self.last_modified.map(|timestamp| {
+            let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        })
+    }
+}
+
+/// converts days since the unix epoch to a (year, month, day) date
+/// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl fmt::Display for LockedInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(follows) = &self.follows {
+            return write!(f, "follows {}", follows.join("/"));
+        }
+        let mut parts = Vec::new();
+        if let (Some(owner), Some(repo)) = (&self.owner, &self.repo) {
+            parts.push(format!("{}/{}", owner, repo));
+        }
+        if let Some(rev) = self.short_rev() {
+            parts.push(rev.to_string());
+        }
+        if let Some(date) = self.date() {
+            parts.push(format!("({})", date));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl fmt::Display for InputStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(locked) = &self.locked {
+            write!(f, "  {}", locked)?;
+        }
+        if !self.in_flake {
+            write!(f, "  [only in flake.lock]")?;
+        } else if self.locked.is_none() {
+            write!(f, "  [not locked]")?;
+        }
+        Ok(())
+    }
+}
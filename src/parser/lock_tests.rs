@@ -0,0 +1,135 @@
+use crate::parser::flake::Flake;
+use crate::parser::lock::FlakeLock;
+
+fn inputs_lock() -> FlakeLock {
+    FlakeLock::parse(include_str!("../../test_data/inputs.lock")).unwrap()
+}
+
+#[test]
+pub fn check_locked_inputs() {
+    let lock = inputs_lock();
+    assert_eq!(
+        lock.input_names(),
+        vec!["hello", "nixCargoIntegration", "nixpkgs", "pkgs", "stale"]
+    );
+
+    let nixpkgs = lock.input("nixpkgs").unwrap();
+    assert_eq!(
+        nixpkgs.rev.as_deref(),
+        Some("4d8dd0afd2d5a35acb5e34c5c7b1674b74173d87")
+    );
+    assert_eq!(
+        nixpkgs.nar_hash.as_deref(),
+        Some("sha256-BGk70gXBXAe9ESnZVVfuUu1OmyiJI1wb8FIm/Pco/7Q=")
+    );
+    assert_eq!(nixpkgs.last_modified, Some(1620800587));
+    assert_eq!(nixpkgs.to_string(), "NixOS/nixpkgs 4d8dd0a (2021-05-12)");
+
+    // follows go through nixCargoIntegration, which itself follows nixpkgs
+    let pkgs = lock.input("pkgs").unwrap();
+    assert_eq!(pkgs.node, "nixpkgs");
+    assert_eq!(pkgs.to_string(), "follows nixCargoIntegration/nixpkgs");
+    assert_eq!(pkgs.rev, nixpkgs.rev);
+
+    assert_eq!(lock.input("missing"), None);
+}
+
+#[test]
+pub fn check_lock_statuses() {
+    let flake = Flake::parse(include_str!("../../test_data/inputs.nix").to_string()).unwrap();
+    let statuses = inputs_lock()
+        .statuses(&flake)
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        vec![
+            "nixpkgs  NixOS/nixpkgs 4d8dd0a (2021-05-12)",
+            "nixCargoIntegration  yusdacra/nix-cargo-integration 9517d2c (2021-05-12)",
+            "hello  numtide/hello b543720 (2021-05-11)",
+            "another_one  [not locked]",
+            "pkgs  follows nixCargoIntegration/nixpkgs  [only in flake.lock]",
+            "stale  yusdacra/stale e10f718 (2021-04-26)  [only in flake.lock]",
+        ]
+    );
+}
+
+#[test]
+pub fn unsupported_lock_versions() {
+    let lock = include_str!("../../test_data/inputs.lock");
+    assert!(FlakeLock::parse(&lock.replace("\"version\": 7", "\"version\": 6")).is_ok());
+    assert!(FlakeLock::parse(&lock.replace("\"version\": 7", "\"version\": 4")).is_err());
+    assert!(FlakeLock::parse(&lock.replace("\"root\": \"root\"", "\"root\": \"other\"")).is_err());
+    assert!(FlakeLock::parse("{").is_err());
+}
+
+#[test]
+pub fn lock_round_trip() {
+    let lock = include_str!("../../test_data/inputs.lock");
+    assert_eq!(FlakeLock::parse(lock).unwrap().to_json(), lock);
+}
+
+#[test]
+pub fn remove_locked_inputs() {
+    let lock = inputs_lock();
+    let json =
+        |lock: &FlakeLock| serde_json::from_str::<serde_json::Value>(&lock.to_json()).unwrap();
+
+    // stale's own inputs go away with it
+    let removed = lock.remove_input("stale");
+    assert_eq!(
+        removed.input_names(),
+        vec!["hello", "nixCargoIntegration", "nixpkgs", "pkgs"]
+    );
+    let nodes = json(&removed)["nodes"].as_object().unwrap().clone();
+    assert!(!nodes.contains_key("stale"));
+    assert!(!nodes.contains_key("flake-utils"));
+
+    // nixpkgs is still an input of the flake
+    let removed = lock.remove_input("nixCargoIntegration");
+    assert!(json(&removed)["nodes"]["nixpkgs"].is_object());
+    assert!(json(&removed)["nodes"]["nixCargoIntegration"].is_null());
+    assert_eq!(removed.input("nixpkgs"), lock.input("nixpkgs"));
+    // follows going through it point to the node they were locked to
+    assert_eq!(json(&removed)["nodes"]["root"]["inputs"]["pkgs"], "nixpkgs");
+    assert_eq!(
+        removed.input("pkgs").unwrap().rev,
+        lock.input("pkgs").unwrap().rev
+    );
+
+    // nixCargoIntegration's follows of nixpkgs keeps the node it was locked to
+    let removed = lock.remove_input("nixpkgs");
+    assert_eq!(
+        json(&removed)["nodes"]["nixCargoIntegration"]["inputs"]["nixpkgs"],
+        "nixpkgs"
+    );
+    assert!(json(&removed)["nodes"]["nixpkgs"].is_object());
+    assert_eq!(removed.input("nixpkgs"), None);
+    assert_eq!(removed.input("pkgs"), lock.input("pkgs"));
+
+    // nodes reached through follows stay, the follows only pointed to them
+    let removed = lock.remove_input("pkgs");
+    assert_eq!(removed.input("pkgs"), None);
+    assert_eq!(json(&removed)["nodes"].as_object().unwrap().len(), 6);
+
+    assert_eq!(lock.remove_input("missing"), lock);
+    assert_eq!(lock.prune(), lock);
+}
+
+#[test]
+pub fn rename_locked_inputs() {
+    let lock = inputs_lock();
+    let renamed = lock.rename_input("nixCargoIntegration", "nci");
+    assert_eq!(
+        renamed.input_names(),
+        vec!["hello", "nci", "nixpkgs", "pkgs", "stale"]
+    );
+    assert_eq!(renamed.input("nci"), lock.input("nixCargoIntegration"));
+    // the follows going through it are renamed too
+    let pkgs = renamed.input("pkgs").unwrap();
+    assert_eq!(pkgs.to_string(), "follows nci/nixpkgs");
+    assert_eq!(pkgs.rev, lock.input("nixpkgs").unwrap().rev);
+    // but not the follows of its own inputs, which don't go through it
+    assert_eq!(renamed.rename_input("nci", "nixCargoIntegration"), lock);
+}
@@ -1,5 +1,28 @@
+pub mod diff;
+pub mod error;
 pub mod file;
+pub mod flake;
+pub mod flake_ref;
+pub mod format;
+pub mod lock;
+pub mod packages;
 pub mod utils;
 
+#[cfg(test)]
+mod diff_tests;
+#[cfg(test)]
+mod file_tests;
+#[cfg(test)]
+mod flake_ref_tests;
+#[cfg(test)]
+mod flake_tests;
+#[cfg(test)]
+mod format_tests;
+#[cfg(test)]
+mod golden_tests;
+#[cfg(test)]
+mod lock_tests;
+#[cfg(test)]
+mod packages_tests;
 #[cfg(test)]
 mod utils_tests;
@@ -0,0 +1,195 @@
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// directory under `$XDG_CACHE_HOME` the index is cached in
+const CACHE_DIR: &str = "flake_generator";
+const CACHE_FILE: &str = "packages.json";
+
+/// A package of nixpkgs, as listed by `nix search` or `nix-env -qaP`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    /// attribute path in nixpkgs, e.g. `python3Packages.pip`
+    pub attr: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Packages of nixpkgs known without going through the network, sorted by attribute path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageIndex {
+    packages: Vec<Package>,
+}
+
+/// the attribute path in nixpkgs of a package listed under `key`
+/// `nix search` lists `legacyPackages.x86_64-linux.hello`,
+/// `nix-env -qaP` lists `nixpkgs.hello` or `nixos.hello`, after the channel
+fn package_attr(key: &str) -> &str {
+    let mut components = key.splitn(3, '.');
+    match (components.next(), components.next(), components.next()) {
+        (Some("legacyPackages"), Some(_), Some(attr)) | (Some("packages"), Some(_), Some(attr)) => {
+            attr
+        }
+        (Some(channel), Some(_), _)
+            if channel.starts_with("nixpkgs") || channel.starts_with("nixos") =>
+        {
+            &key[channel.len() + 1..]
+        }
+        _ => key,
+    }
+}
+
+/// where the index is cached: `$XDG_CACHE_HOME/flake_generator/packages.json`,
+/// `$XDG_CACHE_HOME` being `~/.cache` if it isn't set
+pub fn cache_path() -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache_home.join(CACHE_DIR).join(CACHE_FILE))
+}
+
+/// how well `query` matches `text`, `None` if its characters don't all appear in order
+/// characters following each other or starting a word score higher
+fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text = text.chars().collect::<Vec<_>>();
+    let mut score = 0;
+    let mut idx = 0;
+    let mut previous: Option<usize> = None;
+    for query_char in query.chars().flat_map(char::to_lowercase) {
+        let found = (idx..text.len()).find(|&idx| text[idx].to_lowercase().eq(Some(query_char)))?;
+        let starts_word = found == 0
+            || !text[found - 1].is_alphanumeric()
+            || (text[found - 1].is_lowercase() && text[found].is_uppercase());
+        score += 1;
+        if previous.map_or(false, |previous| previous + 1 == found) {
+            score += 5;
+        }
+        if starts_word {
+            score += 3;
+        }
+        previous = Some(found);
+        idx = found + 1;
+    }
+    Some(score)
+}
+
+impl PackageIndex {
+    /// parses the output of `nix search --json nixpkgs` or `nix-env -qaP --json`
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let json: Value = serde_json::from_str(content)
+            .map_err(|err| anyhow!("could not parse the package index: {}", err))?;
+        let entries = json
+            .as_object()
+            .ok_or_else(|| anyhow!("the package index is not a JSON object"))?;
+        let mut packages = entries
+            .iter()
+            .map(|(key, package)| {
+                // `nix-env` puts the description in `meta`
+                let field = |name: &str| {
+                    package
+                        .get(name)
+                        .or_else(|| package.get("meta").and_then(|meta| meta.get(name)))
+                        .and_then(Value::as_str)
+                        .filter(|value| !value.is_empty())
+                        .map(str::to_string)
+                };
+                Package {
+                    attr: package_attr(key).to_string(),
+                    version: field("version"),
+                    description: field("description"),
+                }
+            })
+            .collect::<Vec<_>>();
+        packages.sort_by(|a, b| a.attr.cmp(&b.attr));
+        packages.dedup_by(|a, b| a.attr == b.attr);
+        Ok(Self { packages })
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| anyhow!("could not read {}: {}", path.display(), err))?;
+        Self::parse(&content)
+    }
+
+    /// the index in `path` if there is one, or else the cached one
+    /// returns `None` if no index was given and none is cached
+    pub fn load(path: Option<&str>) -> anyhow::Result<Option<Self>> {
+        match path {
+            Some(path) => Self::read(Path::new(path)).map(Some),
+            None => match cache_path() {
+                Some(cache) if cache.exists() => Self::read(&cache).map(Some),
+                _ => Ok(None),
+            },
+        }
+    }
+
+    /// checks the index in `path` and copies it to the cache, where `load` finds it
+    /// returns the path of the cached index
+    pub fn import(path: &str) -> anyhow::Result<PathBuf> {
+        let content =
+            fs::read_to_string(path).map_err(|err| anyhow!("could not read {}: {}", path, err))?;
+        Self::parse(&content)?;
+        let cache = match cache_path() {
+            Some(cache) => cache,
+            None => bail!("neither XDG_CACHE_HOME nor HOME is set"),
+        };
+        if let Some(dir) = cache.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|err| anyhow!("could not create {}: {}", dir.display(), err))?;
+        }
+        fs::write(&cache, content)
+            .map_err(|err| anyhow!("could not write {}: {}", cache.display(), err))?;
+        Ok(cache)
+    }
+
+    pub fn packages(&self) -> &[Package] {
+        &self.packages
+    }
+
+    pub fn package(&self, attr: &str) -> Option<&Package> {
+        self.packages
+            .binary_search_by(|package| package.attr.as_str().cmp(attr))
+            .ok()
+            .map(|idx| &self.packages[idx])
+    }
+
+    /// the packages whose attribute path fuzzily matches `query`, best matches first,
+    /// followed by those whose description contains it
+    pub fn search(&self, query: &str) -> Vec<&Package> {
+        let lowercase_query = query.to_lowercase();
+        let mut matches = self
+            .packages
+            .iter()
+            .filter_map(|package| {
+                let score = fuzzy_score(query, &package.attr).or_else(|| {
+                    package
+                        .description
+                        .as_ref()
+                        .filter(|description| description.to_lowercase().contains(&lowercase_query))
+                        .map(|_| 0)
+                })?;
+                Some((package, score))
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|(package, score)| (Reverse(*score), package.attr.len()));
+        matches.into_iter().map(|(package, _)| package).collect()
+    }
+}
+
+impl fmt::Display for Package {
+    /// the attribute path first, so that it can be told apart in a list of packages
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.attr)?;
+        if let Some(version) = &self.version {
+            write!(f, " {}", version)?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, " - {}", description.lines().next().unwrap_or_default())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use crate::parser::packages::{Package, PackageIndex};
+
+fn packages_index() -> PackageIndex {
+    PackageIndex::parse(include_str!("../../test_data/packages.json")).unwrap()
+}
+
+fn attrs(packages: Vec<&Package>) -> Vec<&str> {
+    packages
+        .into_iter()
+        .map(|package| package.attr.as_str())
+        .collect()
+}
+
+#[test]
+pub fn parse_package_indexes() {
+    let index = packages_index();
+    assert_eq!(
+        attrs(index.packages().iter().collect()),
+        vec![
+            "hello",
+            "libressl",
+            "openssl",
+            "pkg-config",
+            "python3Packages.pip",
+            "zlib"
+        ]
+    );
+    assert_eq!(
+        index.package("hello").unwrap().to_string(),
+        "hello 2.10 - A program that produces a familiar, friendly greeting"
+    );
+
+    // `nix-env -qaP --json` lists packages under their channel, with a `meta` attribute
+    let nix_env = PackageIndex::parse(
+        r#"{
+  "nixos.hello": { "name": "hello-2.10", "pname": "hello", "version": "2.10", "meta": { "description": "greeting" } },
+  "nixos.python3Packages.pip": { "name": "pip-20.3.4", "meta": {} }
+}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        nix_env.packages(),
+        &[
+            Package {
+                attr: "hello".to_string(),
+                version: Some("2.10".to_string()),
+                description: Some("greeting".to_string()),
+            },
+            Package {
+                attr: "python3Packages.pip".to_string(),
+                version: None,
+                description: None,
+            },
+        ]
+    );
+
+    assert!(PackageIndex::parse("[]").is_err());
+}
+
+#[test]
+pub fn search_packages() {
+    let index = packages_index();
+    // zlib is "lossless"
+    assert_eq!(
+        attrs(index.search("ssl")),
+        vec!["openssl", "libressl", "zlib"]
+    );
+    assert_eq!(attrs(index.search("pkgconf")), vec!["pkg-config"]);
+    assert_eq!(attrs(index.search("pip")), vec!["python3Packages.pip"]);
+    // descriptions match too, after attribute paths
+    assert_eq!(attrs(index.search("compression")), vec!["zlib"]);
+    assert!(index.search("nothing like it").is_empty());
+}
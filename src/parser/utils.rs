@@ -1,56 +1,448 @@
-use anyhow::anyhow;
-use rnix::{types::*, NixLanguage, StrPart, SyntaxKind::*};
-use rowan::api::SyntaxNode;
+use crate::parser::error::{AstError, Span};
+use crate::parser::flake::Flake;
+use anyhow::{anyhow, bail};
+use rnix::{types::*, NixLanguage, StrPart, SyntaxKind, SyntaxKind::*};
+use rowan::{
+    api::{SyntaxNode, SyntaxToken},
+    GreenNode, GreenToken, Language, NodeOrToken,
+};
 
 pub(crate) type NixNode = SyntaxNode<NixLanguage>;
+pub(crate) type NixToken = SyntaxToken<NixLanguage>;
 
 use std::collections::HashMap;
 
-/// Precondition: node is a attribute and parent is an attribute set
-/// (1) get parent attrset
+/// Precondition: node is a attribute and parent is an attribute set,
+/// or node is an element of a list
+/// (1) get parent attrset or list
 /// (2) iterate through parent's children nodes, searching for node to delete
-/// (4) return a modified tree with node deleted
+/// (3) extend the range to the comments above and after the node
+/// (4) return a modified tree with node deleted, and the whitespace around it collapsed
 /// if child node is not found in parent, something is very wrong
-/// so error out/fail, and not in a graceful manner
+/// so error out with `AstError::InconsistentTree`
 /// amount parameter specifies number of nodes/tokens to kill
 pub fn kill_node_attribute(node: &NixNode, amount: usize) -> anyhow::Result<NixNode> {
-    let parent = node.parent().unwrap();
+    let parent = node
+        .parent()
+        .ok_or_else(|| AstError::InconsistentTree("attribute has no parent".to_string()))?;
     match parent.kind() {
-        NODE_ATTR_SET | NODE_PATTERN => {
-            let mut child_node_idxs =
-                parent
-                    .green()
-                    .children()
-                    .enumerate()
-                    .filter_map(|(idx, val)| {
-                        // the '.to_owned()' is required to turn GreenNodeData into GreenNode
-                        // because GreenNodeData doesn't implement PartialEq
-                        val.into_node().and_then(|inner_node| {
-                            (*inner_node == node.green().to_owned()).then(|| idx)
-                        })
-                    });
-            // only one child node should match
-            let idx = child_node_idxs.next().expect("Child not in parent tree");
-            assert!(
-                child_node_idxs.next().is_none(),
-                "AST in inconsistent state. Child found multiple times in parent tree."
-            );
-            let result = node
-                .parent()
-                .unwrap()
-                .green()
-                .splice_children(idx..idx + amount, std::iter::empty());
-            let mut new_root = NixNode::new_root(parent.replace_with(result));
-            while let Some(parent) = new_root.parent() {
-                new_root = parent;
+        NODE_ATTR_SET | NODE_PATTERN | NODE_LIST => {
+            let children = parent.children_with_tokens().collect::<Vec<_>>();
+            let index_of = |element: &NodeOrToken<NixNode, NixToken>| {
+                children
+                    .iter()
+                    .position(|child| child == element)
+                    .ok_or_else(|| {
+                        AstError::InconsistentTree("child not in parent tree".to_string())
+                    })
+            };
+            // syntax nodes compare by identity, so at most one child matches
+            let idx = index_of(&NodeOrToken::Node(node.clone()))?;
+            let start = match leading_comments(node).into_iter().next() {
+                Some(comment) => index_of(&NodeOrToken::Token(comment))?,
+                None => idx,
+            };
+            let end = match comment_after(children.get(idx + amount).cloned()) {
+                Some(comment) => index_of(&NodeOrToken::Token(comment))? + 1,
+                None => idx + amount,
+            };
+            let whitespace_at = |idx: usize| {
+                children
+                    .get(idx)
+                    .and_then(|child| child.as_token())
+                    .filter(|tok| tok.kind() == TOKEN_WHITESPACE)
+                    .map(|tok| tok.text().to_string())
+            };
+            let before = start.checked_sub(1).and_then(whitespace_at);
+            let after = whitespace_at(end);
+            let range_start = start - before.iter().count();
+            let range_end = end + after.iter().count();
+            let opens = range_start.checked_sub(1).map_or(true, |idx| {
+                matches!(
+                    children[idx].kind(),
+                    TOKEN_CURLY_B_OPEN | TOKEN_SQUARE_B_OPEN
+                )
+            });
+            let closes = children.get(range_end).map_or(true, |child| {
+                matches!(child.kind(), TOKEN_CURLY_B_CLOSE | TOKEN_SQUARE_B_CLOSE)
+            });
+            let before = before.unwrap_or_default();
+            let after = after.unwrap_or_default();
+            let whitespace = if opens {
+                before
+            } else if closes {
+                after
+            } else {
+                joined_whitespace(&before, &after)
+            };
+            Ok(splice_node_children(
+                &parent,
+                range_start..range_end,
+                Some(whitespace)
+                    .filter(|whitespace| !whitespace.is_empty())
+                    .map(|whitespace| make_token(TOKEN_WHITESPACE, &whitespace)),
+            ))
+        }
+        _ => Err(AstError::UnsupportedSyntax(
+            "attribute outside of an attribute set".to_string(),
+            Span::of(&parent),
+        )
+        .into()),
+    }
+}
+
+/// the whitespace left between two entries once what was between them is removed:
+/// a blank line if there was one on either side, and the indentation of the second entry
+fn joined_whitespace(before: &str, after: &str) -> String {
+    let newlines = before
+        .matches('\n')
+        .count()
+        .max(after.matches('\n').count())
+        .min(2);
+    if newlines == 0 {
+        return after.to_string();
+    }
+    let indent_of = |whitespace: &str| {
+        whitespace
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    let indent = if after.contains('\n') {
+        indent_of(after)
+    } else {
+        indent_of(before)
+    };
+    format!("{}{}", "\n".repeat(newlines), indent)
+}
+
+/// replaces the children of `parent` in `range` with `elements`
+/// returns the root of the newly created tree
+fn splice_node_children<I>(parent: &NixNode, range: std::ops::Range<usize>, elements: I) -> NixNode
+where
+    I: IntoIterator<Item = NodeOrToken<GreenNode, GreenToken>>,
+{
+    replace_node(parent, parent.green().splice_children(range, elements))
+}
+
+/// replaces `node` with `replacement`
+/// returns the root of the newly created tree
+fn replace_node(node: &NixNode, replacement: GreenNode) -> NixNode {
+    let new_root = NixNode::new_root(node.replace_with(replacement));
+    Root::cast(new_root).unwrap().inner().unwrap()
+}
+
+/// replaces every node of `replacements` at once, rebuilding the tree in a single walk
+/// the nodes must all belong to the same tree as `root`
+/// returns the root of the newly created tree
+fn replace_nodes(root: &NixNode, replacements: &[(NixNode, GreenNode)]) -> NixNode {
+    fn rebuild(node: &NixNode, replacements: &[(NixNode, GreenNode)]) -> GreenNode {
+        if let Some((_, replacement)) = replacements.iter().find(|(target, _)| target == node) {
+            return replacement.clone();
+        }
+        let is_untouched = !replacements
+            .iter()
+            .any(|(target, _)| target.ancestors().any(|ancestor| ancestor == *node));
+        if is_untouched {
+            return node.green().to_owned();
+        }
+        let children = node
+            .children_with_tokens()
+            .map(|child| match child {
+                NodeOrToken::Node(child) => NodeOrToken::Node(rebuild(&child, replacements)),
+                NodeOrToken::Token(token) => NodeOrToken::Token(token.green().to_owned()),
+            })
+            .collect::<Vec<_>>();
+        GreenNode::new(NixLanguage::kind_to_raw(node.kind()), children)
+    }
+    let top = root.ancestors().last().unwrap_or_else(|| root.clone());
+    let new_root = NixNode::new_root(rebuild(&top, replacements));
+    Root::cast(new_root).unwrap().inner().unwrap()
+}
+
+/// creates a detached token of the given kind
+fn make_token(kind: SyntaxKind, text: &str) -> NodeOrToken<GreenNode, GreenToken> {
+    NodeOrToken::Token(GreenToken::new(NixLanguage::kind_to_raw(kind), text))
+}
+
+/// parses `snippet` and returns the first node of the given kind
+/// used to build nodes that are spliced into an existing tree
+fn parse_snippet(snippet: &str, kind: SyntaxKind) -> anyhow::Result<NixNode> {
+    string_to_node(snippet.to_string())?
+        .descendants()
+        .find(|node| node.kind() == kind)
+        .ok_or_else(|| anyhow!("could not build {:?} from {}", kind, snippet))
+}
+
+/// returns the whitespace that sits at the start of the line `node` begins on
+pub(crate) fn line_indent(node: &NixNode) -> String {
+    let root = node.ancestors().last().unwrap_or_else(|| node.clone());
+    let text = root.to_string();
+    let start = usize::from(node.text_range().start());
+    let line_start = text[..start].rfind('\n').map_or(0, |idx| idx + 1);
+    text[line_start..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect()
+}
+
+/// the separator to put in front of a new sibling of `node`
+/// new lines are kept if `node` is on its own line, blank lines are not
+fn sibling_separator(node: &NixNode) -> String {
+    match node.prev_sibling_or_token() {
+        Some(NodeOrToken::Token(tok))
+            if tok.kind() == TOKEN_WHITESPACE && tok.text().contains('\n') =>
+        {
+            format!("\n{}", line_indent(node))
+        }
+        _ => " ".to_string(),
+    }
+}
+
+/// escapes `content` so that it can be used as a nix string literal
+pub fn to_nix_string(content: &str) -> String {
+    let escaped = content
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{}\"", escaped)
+}
+
+/// returns the content of a string literal, or `None` if it isn't one
+/// or if it contains interpolations
+pub(crate) fn string_literal(node: &NixNode) -> Option<String> {
+    Str::cast(node.clone())?
+        .parts()
+        .into_iter()
+        .try_fold(String::new(), |mut acc, ele| match ele {
+            StrPart::Literal(s) => {
+                acc.push_str(&s);
+                Some(acc)
             }
-            let tmp = Root::cast(new_root).unwrap();
-            Ok(tmp.inner().unwrap())
+            StrPart::Ast(_) => None,
+        })
+}
+
+/// returns the name of one element of an attribute path
+/// identifiers and strings without interpolation give their content,
+/// anything else, such as `${foo}`, gives its source text
+fn key_name(node: NixNode) -> String {
+    match Ident::cast(node.clone()) {
+        Some(ident) => ident.as_str().to_string(),
+        None => string_literal(&node).unwrap_or_else(|| node.to_string()),
+    }
+}
+
+/// returns the key of an attribute as a list of identifiers
+/// e.g. `inputs.nixpkgs.url` gives `["inputs", "nixpkgs", "url"]`
+pub(crate) fn key_path(entry: &KeyValue) -> Vec<String> {
+    entry
+        .key()
+        .map_or_else(Vec::new, |key| key.path().map(key_name).collect())
+}
+
+/// the comments on the lines right above `node`, up to a blank line
+/// a comment following another entry on the same line belongs to that entry
+pub(crate) fn leading_comments(node: &NixNode) -> Vec<NixToken> {
+    let mut comments = Vec::new();
+    let mut current = node.prev_sibling_or_token();
+    while let Some(NodeOrToken::Token(tok)) = current {
+        match tok.kind() {
+            TOKEN_WHITESPACE if tok.text().matches('\n').count() > 1 => break,
+            TOKEN_WHITESPACE => {}
+            TOKEN_COMMENT => {
+                let trails_entry = match tok.prev_sibling_or_token() {
+                    Some(NodeOrToken::Token(prev)) if prev.kind() == TOKEN_WHITESPACE => {
+                        !prev.text().contains('\n')
+                            && matches!(prev.prev_sibling_or_token(), Some(NodeOrToken::Node(_)))
+                    }
+                    Some(NodeOrToken::Node(_)) => true,
+                    _ => false,
+                };
+                if trails_entry {
+                    break;
+                }
+                comments.push(tok.clone());
+            }
+            _ => break,
+        }
+        current = tok.prev_sibling_or_token();
+    }
+    comments.reverse();
+    comments
+}
+
+/// the comment following `node` on the same line, if any
+pub(crate) fn trailing_comment(node: &NixNode) -> Option<NixToken> {
+    comment_after(node.next_sibling_or_token())
+}
+
+/// the comment starting at `next`, if it is on the same line
+fn comment_after(next: Option<NodeOrToken<NixNode, NixToken>>) -> Option<NixToken> {
+    let mut next = next?;
+    if let NodeOrToken::Token(tok) = &next {
+        if tok.kind() == TOKEN_WHITESPACE && !tok.text().contains('\n') {
+            next = tok.next_sibling_or_token()?;
+        }
+    }
+    next.into_token().filter(|tok| tok.kind() == TOKEN_COMMENT)
+}
+
+/// `name` as an element of an attribute path, quoted if it isn't an identifier
+pub(crate) fn attr_name(name: &str) -> String {
+    match make_ident(name) {
+        Ok(_) => name.to_string(),
+        Err(_) => to_nix_string(name),
+    }
+}
+
+/// replaces the entries `dead` of `attr_set`, along with their comments, by `text`,
+/// written where the first of them was
+/// `text` holds entries and comments, every line but the first one indented
+/// returns the root of the newly created tree
+pub(crate) fn replace_entries_with_text(
+    attr_set: &NixNode,
+    dead: &[NixNode],
+    text: &str,
+) -> anyhow::Result<NixNode> {
+    let snippet = string_to_node(format!("{{\n{}\n}}", text.trim()))?;
+    let snippet_children = snippet.children_with_tokens().collect::<Vec<_>>();
+    // what's between the braces, without the new lines around it
+    let start = snippet_children
+        .iter()
+        .position(|child| child.kind() == TOKEN_CURLY_B_OPEN)
+        .map_or(0, |idx| idx + 2);
+    let end = snippet_children
+        .iter()
+        .rposition(|child| child.kind() == TOKEN_CURLY_B_CLOSE)
+        .map_or(0, |idx| idx - 1);
+    let new_children = snippet_children
+        .get(start..end)
+        .ok_or_else(|| anyhow!("could not build the entries from {}", text))?
+        .iter()
+        .map(|child| match child {
+            NodeOrToken::Node(node) => NodeOrToken::Node(node.green().to_owned()),
+            NodeOrToken::Token(tok) => NodeOrToken::Token(tok.green().to_owned()),
+        })
+        .collect::<Vec<_>>();
+
+    let children = attr_set.children_with_tokens().collect::<Vec<_>>();
+    let index_of = |element: &NodeOrToken<NixNode, NixToken>| {
+        children
+            .iter()
+            .position(|child| child == element)
+            .ok_or_else(|| AstError::InconsistentTree("entry not in attribute set".to_string()))
+    };
+    // ranges of children to remove, comments included
+    let mut ranges = Vec::new();
+    for entry in dead {
+        let first = leading_comments(entry)
+            .into_iter()
+            .next()
+            .map(NodeOrToken::Token)
+            .unwrap_or_else(|| NodeOrToken::Node(entry.clone()));
+        let last = trailing_comment(entry)
+            .map(NodeOrToken::Token)
+            .unwrap_or_else(|| NodeOrToken::Node(entry.clone()));
+        ranges.push((index_of(&first)?, index_of(&last)?));
+    }
+    ranges.sort_unstable();
+    let insert_at = ranges
+        .first()
+        .map(|(start, _)| *start)
+        .ok_or_else(|| anyhow!("no entries to replace"))?;
+
+    let mut result = Vec::new();
+    for (idx, child) in children.iter().enumerate() {
+        if idx == insert_at {
+            result.extend(new_children.iter().cloned());
+        }
+        let removed = ranges
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&idx));
+        // the whitespace in front of a removed entry goes with it, except for the first one
+        let removed_whitespace = child.kind() == TOKEN_WHITESPACE
+            && idx + 1 != insert_at
+            && ranges.iter().any(|(start, _)| *start == idx + 1);
+        if removed || removed_whitespace {
+            continue;
+        }
+        result.push(match child {
+            NodeOrToken::Node(node) => NodeOrToken::Node(node.green().to_owned()),
+            NodeOrToken::Token(tok) => NodeOrToken::Token(tok.green().to_owned()),
+        });
+    }
+    Ok(replace_node(
+        attr_set,
+        GreenNode::new(NixLanguage::kind_to_raw(attr_set.kind()), result),
+    ))
+}
+
+/// inserts `entries` into `attr_set` after `after`,
+/// or at the start of the attribute set if `after` is `None`
+/// returns the root of the newly created tree
+fn insert_entries(
+    attr_set: &NixNode,
+    after: Option<&NixNode>,
+    entries: &[NixNode],
+) -> anyhow::Result<NixNode> {
+    let children = attr_set.children_with_tokens().collect::<Vec<_>>();
+    let (mut idx, separator) = match after {
+        Some(after) => (
+            children
+                .iter()
+                .position(|child| child.as_node() == Some(after))
+                .ok_or_else(|| anyhow!("entry not in attribute set"))?
+                + 1,
+            sibling_separator(after),
+        ),
+        None => (
+            children
+                .iter()
+                .position(|child| child.kind() == TOKEN_CURLY_B_OPEN)
+                .ok_or_else(|| anyhow!("attribute set has no opening brace"))?
+                + 1,
+            format!("\n{}  ", line_indent(attr_set)),
+        ),
+    };
+    // keep comments trailing the previous entry on the same line with it
+    while let Some(child) = children.get(idx) {
+        let on_same_line = match child {
+            NodeOrToken::Token(tok) if tok.kind() == TOKEN_WHITESPACE => !tok.text().contains('\n'),
+            NodeOrToken::Token(tok) => tok.kind() == TOKEN_COMMENT,
+            NodeOrToken::Node(_) => false,
+        };
+        if !on_same_line
+            || children
+                .get(idx + 1)
+                .map_or(false, |next| next.kind() == TOKEN_CURLY_B_CLOSE)
+        {
+            break;
+        }
+        idx += 1;
+    }
+
+    let mut new_children = Vec::new();
+    for entry in entries {
+        new_children.push(make_token(TOKEN_WHITESPACE, &separator));
+        new_children.push(NodeOrToken::Node(entry.green().to_owned()));
+    }
+    // an empty attribute set has nothing to separate the new entries from the closing brace
+    if after.is_none()
+        && AttrSet::cast(attr_set.clone()).map_or(false, |set| set.entries().next().is_none())
+    {
+        let closing_on_own_line = children.get(idx).map_or(false, |child| {
+            child.kind() == TOKEN_WHITESPACE && child.to_string().contains('\n')
+        });
+        if !closing_on_own_line {
+            new_children.push(make_token(
+                TOKEN_WHITESPACE,
+                &format!("\n{}", line_indent(attr_set)),
+            ));
         }
-        _ => Err(anyhow!(
-            "Precondition violated: parent was not attribute set."
-        )),
     }
+    Ok(splice_node_children(attr_set, idx..idx, new_children))
 }
 
 /// converts a AST node to a string.
@@ -74,14 +466,16 @@ pub fn node_to_string(node: NixNode) -> String {
 pub fn string_to_node(content: String) -> anyhow::Result<NixNode> {
     rnix::parse(&content)
         .as_result()
-        .map(|ast| ast.root().inner().unwrap())
-        .map_err(|err| anyhow!("could not parse as a nix file: {}", err))
+        .map_err(|err| anyhow!("could not parse as a nix file: {}", err))?
+        .root()
+        .inner()
+        .ok_or_else(|| anyhow!("could not parse as a nix file: the file is empty"))
 }
 
 /// given an attribute name, searches to max_depth
 /// for the given attribute name
-/// it is assumed that the node's max depth >= exact_depth
-/// and root_node is of type attrset
+/// nothing can match if exact_depth > max_depth
+/// and root_node is of type attrset, otherwise nothing is found
 /// returns a vector of tuples that match
 /// (matching_node, path, depth)
 /// Example: searching for `foo`
@@ -95,15 +489,7 @@ fn search_for_attr(
     root_node: &NixNode,
     exact_depth: Option<usize>,
 ) -> Vec<(NixNode, String, usize)> {
-    if let Some(x) = exact_depth {
-        assert!(
-            max_depth >= x,
-            "it is assumed that the node's max depth >= exact depth"
-        );
-    }
-
     // assuming that the root node is an attrset
-    // TODO if it's not, we should fail louder
     let mut stack = match AttrSet::cast((*root_node).clone()) {
         Some(rn) => rn.entries().map(|x| (x, String::new(), 0)).collect(),
         None => Vec::new(),
@@ -112,21 +498,26 @@ fn search_for_attr(
     let mut result = Vec::new();
 
     while let Some((cur_node, mut path, mut cur_depth)) = stack.pop() {
-        let cur_node_value = cur_node.value().unwrap();
-        let cur_node_key = cur_node.key().unwrap();
+        let cur_node_value = match cur_node.value() {
+            Some(value) => value,
+            None => continue,
+        };
+        let cur_node_key = match cur_node.key() {
+            Some(key) => key,
+            None => continue,
+        };
 
         if cur_depth > max_depth {
             // failing softly here since we're past the max depth
             // might want to be a bit more loud
-            return vec![];
+            continue;
         }
 
         let mut cur_node_attribute = String::new();
         let mut is_match = false;
 
         for p in cur_node_key.path() {
-            let tmp = Ident::cast(p).unwrap();
-            let cur_attr = tmp.as_str();
+            let cur_attr = key_name(p);
             cur_node_attribute.push('.');
             cur_node_attribute.push_str(&cur_attr);
             cur_depth += 1;
@@ -155,57 +546,490 @@ fn search_for_attr(
     result
 }
 
-/// searches AST for input nodes
-/// returns hashmap of value to node
-/// for example { "github.com/foo/bar": Node(FooBar)}
-pub fn get_inputs(root: &NixNode) -> HashMap<String, NixNode> {
-    search_for_attr("inputs", 1, root, None)
+/// searches AST for an attribute of every input, such as `url` or `follows`
+/// expected_depth is the length of the full attribute path,
+/// e.g. 3 for `inputs.{}.url` or 5 for `inputs.{}.inputs.{}.follows`
+/// returns hashmap of attribute path to value node
+fn get_input_attrs(root: &NixNode, attr: &str, expected_depth: usize) -> HashMap<String, NixNode> {
+    let suffix = format!(".{}", attr);
+    let top_level = match top_level_attrs(root) {
+        Some(top_level) => top_level.node().clone(),
+        None => return HashMap::new(),
+    };
+    search_for_attr("inputs", 1, &top_level, None)
         .into_iter()
         .flat_map(|(ele, attribute_path, depth)| {
-            // inputs.{}.url: we expect the depth to be 3
-            const EXPECTED_DEPTH: usize = 3;
             match ele.kind() {
-                // edge case of entire attribute set at once. E.g. inputs.nixpkgs.url = "foo";
-                NODE_STRING => {
-                    if depth == EXPECTED_DEPTH {
-                        vec![(attribute_path, ele)]
-                    } else {
-                        vec![]
-                    }
-                }
                 // common case of { nixpkgs = { url = "foo"; }; }
-                NODE_ATTR_SET => search_for_attr("url", 2, &ele, None)
+                NODE_ATTR_SET => search_for_attr(attr, expected_depth - 1, &ele, None)
                     .into_iter()
                     .filter_map(|(n_ele, path, n_depth)| {
-                        (depth + n_depth == EXPECTED_DEPTH)
+                        (depth + n_depth == expected_depth)
                             .then(|| (format!("{}{}", attribute_path, path), n_ele))
                     })
                     .collect(),
-                _ => vec![],
+                // edge case of entire attribute set at once. E.g. inputs.nixpkgs.url = "foo";
+                _ => {
+                    if depth == expected_depth {
+                        vec![(attribute_path, ele)]
+                    } else {
+                        vec![]
+                    }
+                }
             }
         })
+        .filter(|(path, _)| path.ends_with(&suffix))
+        .collect()
+}
+
+/// searches AST for input nodes
+/// returns hashmap of value to node
+/// for example { "github.com/foo/bar": Node(FooBar)}
+/// inputs written as attribute sets, e.g. `inputs.foo = { type = "github"; ... };`,
+/// are found by their type, e.g. { ".inputs.foo.type": Node("github") },
+/// since the `url` of `type = "git";` is only part of the reference
+pub fn get_inputs(root: &NixNode) -> HashMap<String, NixNode> {
+    let mut inputs = get_input_attrs(root, "type", 3);
+    // inputs.{}.url: we expect the depth to be 3
+    for (path, node) in get_input_attrs(root, "url", 3) {
+        let name = get_attr(1, &path);
+        if !inputs
+            .keys()
+            .any(|type_path| get_attr(1, type_path) == name)
+        {
+            inputs.insert(path, node);
+        }
+    }
+    inputs
+}
+
+/// the node of the url of an input, or of its type if it's written as an attribute set
+fn input_ref_node<'a>(inputs: &'a HashMap<String, NixNode>, name: &str) -> Option<&'a NixNode> {
+    inputs
+        .get(&format!(".inputs.{}.type", name))
+        .or_else(|| inputs.get(&format!(".inputs.{}.url", name)))
+}
+
+/// searches AST for follows of the inputs' own inputs
+/// returns hashmap of attribute path to the followed input's node
+/// for example { ".inputs.foo.inputs.nixpkgs.follows": Node("nixpkgs") }
+pub fn get_follows(root: &NixNode) -> HashMap<String, NixNode> {
+    // inputs.{}.inputs.{}.follows: we expect the depth to be 5
+    get_input_attrs(root, "follows", 5)
+        .into_iter()
+        .filter(|(path, _)| get_attr(2, path) == Some("inputs"))
+        .collect()
+}
+
+/// names of the inputs marked with `flake = false`
+fn get_non_flake_inputs(root: &NixNode) -> Vec<String> {
+    get_input_attrs(root, "flake", 3)
+        .into_iter()
+        .filter(|(_, node)| node.to_string() == "false")
+        .filter_map(|(path, _)| get_attr(1, &path).map(str::to_string))
         .collect()
 }
 
 // exists for test usage
 #[cfg(test)]
 pub fn get_output_node(root: &NixNode) -> anyhow::Result<Lambda> {
-    Ok(Lambda::cast(search_for_attr("outputs", 2, root, None).remove(0).0).unwrap())
+    Ok(get_output_fn(root)?)
+}
+
+/// how many parentheses, `let ... in` and variables `resolve_value` goes through
+/// bounds the search, since `let x = x; in x` never resolves
+const MAX_RESOLVE_DEPTH: usize = 32;
+
+/// the value bound to the variable `ident` by an enclosing `let ... in`, if any
+fn let_binding(ident: &NixNode) -> Option<NixNode> {
+    let name = Ident::cast(ident.clone())?.as_str().to_string();
+    ident
+        .ancestors()
+        .filter_map(LetIn::cast)
+        .find_map(|let_in| {
+            let_in
+                .entries()
+                .find(|entry| key_path(entry) == [name.as_str()])
+                .and_then(|entry| entry.value())
+        })
+}
+
+/// goes through parentheses, `let ... in` and variables bound by `let`
+/// to find the expression `node` stands for, without evaluating anything
+/// e.g. `let out = inputs: {}; in (out)` gives `inputs: {}`
+fn resolve_value(mut node: NixNode) -> NixNode {
+    for _ in 0..MAX_RESOLVE_DEPTH {
+        let next = match node.kind() {
+            NODE_PAREN => Paren::cast(node.clone()).and_then(|paren| paren.inner()),
+            NODE_LET_IN => LetIn::cast(node.clone()).and_then(|let_in| let_in.body()),
+            NODE_IDENT => let_binding(&node),
+            _ => None,
+        };
+        match next {
+            Some(next) => node = next,
+            None => break,
+        }
+    }
+    node
+}
+
+/// the attribute set of the flake, which may be wrapped in a `let ... in`
+pub(crate) fn top_level_attrs(root: &NixNode) -> Option<AttrSet> {
+    AttrSet::cast(resolve_value(root.clone()))
+}
+
+/// the outputs function of the flake, which may be bound by a `let ... in`
+pub(crate) fn get_output_fn(root: &NixNode) -> Result<Lambda, AstError> {
+    let top_level = top_level_attrs(root).ok_or(AstError::MissingOutputs)?;
+    let outputs = top_level
+        .entries()
+        .find(|entry| key_path(entry) == ["outputs"])
+        .and_then(|entry| entry.value())
+        // `inherit outputs;`, from a `let ... in`
+        .or_else(|| {
+            top_level
+                .inherits()
+                .filter(|inherit| inherit.from().is_none())
+                .flat_map(|inherit| inherit.idents())
+                .find(|ident| ident.as_str() == "outputs")
+                .map(|ident| ident.node().clone())
+        })
+        .ok_or(AstError::MissingOutputs)?;
+    let resolved = resolve_value(outputs.clone());
+    Lambda::cast(resolved).ok_or_else(|| AstError::OutputsNotLambda(Span::of(&outputs)))
+}
+
+/// names a function gives to its argument
+/// `(name of the whole argument, names of the destructured attributes)`
+/// e.g. `inputs: ...` gives `(Some("inputs"), [])`
+/// and `{ self, nixpkgs }@inputs: ...` gives `(Some("inputs"), ["self", "nixpkgs"])`
+fn lambda_binders(lambda: &Lambda) -> (Option<String>, Vec<String>) {
+    let arg = match lambda.arg() {
+        Some(arg) => arg,
+        None => return (None, Vec::new()),
+    };
+    if let Some(ident) = Ident::cast(arg.clone()) {
+        return (Some(ident.as_str().to_string()), Vec::new());
+    }
+    match Pattern::cast(arg) {
+        Some(pattern) => (
+            pattern.at().map(|at| at.as_str().to_string()),
+            pattern
+                .entries()
+                .filter_map(|entry| entry.name())
+                .map(|name| name.as_str().to_string())
+                .collect(),
+        ),
+        None => (None, Vec::new()),
+    }
+}
+
+/// whether `name` is bound again between `node` and `scope`,
+/// by a `let ... in`, a function argument or a recursive attribute set
+fn is_shadowed(node: &NixNode, name: &str, scope: &NixNode) -> bool {
+    node.ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor != scope)
+        .any(|ancestor| {
+            if let Some(let_in) = LetIn::cast(ancestor.clone()) {
+                binds_name(&let_in, name)
+            } else if let Some(attr_set) = AttrSet::cast(ancestor.clone()) {
+                attr_set.recursive() && binds_name(&attr_set, name)
+            } else if let Some(lambda) = Lambda::cast(ancestor) {
+                let (bind, entries) = lambda_binders(&lambda);
+                bind.as_deref() == Some(name) || entries.iter().any(|entry| entry == name)
+            } else {
+                false
+            }
+        })
+}
+
+/// whether `holder` has an attribute or inherits something called `name`
+fn binds_name<T: EntryHolder>(holder: &T, name: &str) -> bool {
+    holder
+        .entries()
+        .any(|entry| key_path(&entry).first().map(String::as_str) == Some(name))
+        || holder
+            .inherits()
+            .any(|inherit| inherit.idents().any(|ident| ident.as_str() == name))
+}
+
+/// whether the identifier `ident` is used as a variable,
+/// rather than as an attribute name or a binding
+fn is_variable(ident: &NixNode) -> bool {
+    let parent = match ident.parent() {
+        Some(parent) => parent,
+        None => return false,
+    };
+    match parent.kind() {
+        NODE_KEY | NODE_PAT_ENTRY | NODE_PAT_BIND => false,
+        // `foo.ident`
+        NODE_SELECT => parent.first_child().as_ref() == Some(ident),
+        // `foo ? ident`
+        NODE_BIN_OP => {
+            parent.first_child().as_ref() == Some(ident)
+                || !parent
+                    .children_with_tokens()
+                    .any(|child| child.kind() == TOKEN_QUESTION)
+        }
+        // `inherit (foo) ident;`
+        NODE_INHERIT => Inherit::cast(parent).map_or(false, |inherit| inherit.from().is_none()),
+        _ => true,
+    }
+}
+
+/// whether `node` is the variable `bind`, naming the whole argument of `output_fn`
+fn is_whole_argument(node: &NixNode, bind: Option<&str>, output_fn: &Lambda) -> bool {
+    match bind {
+        Some(bind) => {
+            node.kind() == NODE_IDENT
+                && node.to_string() == bind
+                && is_variable(node)
+                && !is_shadowed(node, bind, output_fn.node())
+        }
+        None => false,
+    }
+}
+
+/// whether the whole argument of the outputs function is used for anything
+/// but selecting inputs from it, e.g. `import ./outputs.nix inputs`,
+/// in which case any input may be used
+fn whole_argument_escapes(output_fn: &Lambda) -> bool {
+    let (bind, _) = lambda_binders(output_fn);
+    let body = match output_fn.body() {
+        Some(body) => body,
+        None => return false,
+    };
+    body.descendants()
+        .filter(|node| is_whole_argument(node, bind.as_deref(), output_fn))
+        .any(|node| match node.parent() {
+            Some(parent) => match parent.kind() {
+                // `inputs.hello` and `inherit (inputs) hello;`
+                NODE_SELECT | NODE_INHERIT_FROM => false,
+                // `inputs ? hello`
+                NODE_BIN_OP => !parent
+                    .children_with_tokens()
+                    .any(|child| child.kind() == TOKEN_QUESTION),
+                _ => true,
+            },
+            None => true,
+        })
+}
+
+/// names of the inputs the flake never uses: they aren't arguments of the outputs function,
+/// aren't selected from its whole argument, and no other input follows them
+/// sorted by name
+pub fn unused_inputs(root: &NixNode) -> anyhow::Result<Vec<String>> {
+    let output_fn = get_output_fn(root)?;
+    if whole_argument_escapes(&output_fn) {
+        return Ok(Vec::new());
+    }
+    let (_, args) = lambda_binders(&output_fn);
+    let followed = get_input_attrs(root, "follows", 3)
+        .values()
+        .chain(get_input_attrs(root, "follows", 5).values())
+        .filter_map(string_literal)
+        // `nixpkgs/nixpkgs` follows an input of nixpkgs
+        .filter_map(|target| target.split('/').next().map(str::to_string))
+        .collect::<Vec<_>>();
+
+    let mut names = get_inputs(root)
+        .keys()
+        .filter_map(|path| get_attr(1, path))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+
+    let mut unused = Vec::new();
+    for name in names {
+        if args.contains(&name) || followed.contains(&name) {
+            continue;
+        }
+        if input_references(root, &name)?.is_empty() {
+            unused.push(name);
+        }
+    }
+    Ok(unused)
+}
+
+/// where the body of the outputs function uses an input,
+/// either directly, e.g. `hello` for `{ hello, ... }: ...`,
+/// or through the whole argument, e.g. `inputs.hello` for `inputs: ...`
+/// returns the nodes naming the input
+pub fn input_references(root: &NixNode, input_name: &str) -> anyhow::Result<Vec<NixNode>> {
+    let output_fn = get_output_fn(root)?;
+    let body = match output_fn.body() {
+        Some(body) => body,
+        None => return Ok(Vec::new()),
+    };
+    let (bind, entries) = lambda_binders(&output_fn);
+    let is_entry = entries.iter().any(|entry| entry == input_name);
+
+    let mut references = Vec::new();
+    for node in body.descendants() {
+        let parent = match node.parent() {
+            Some(parent) => parent,
+            None => continue,
+        };
+        let is_reference = match node.kind() {
+            NODE_IDENT if is_variable(&node) => {
+                is_entry
+                    && node.to_string() == input_name
+                    && !is_shadowed(&node, input_name, output_fn.node())
+            }
+            // `inputs.hello`, `inputs ? hello` and `inherit (inputs) hello;`
+            NODE_IDENT | NODE_STRING => {
+                let set = match parent.kind() {
+                    NODE_SELECT | NODE_BIN_OP => parent.first_child(),
+                    NODE_INHERIT => Inherit::cast(parent.clone())
+                        .and_then(|inherit| inherit.from())
+                        .and_then(|from| from.inner()),
+                    _ => None,
+                };
+                set.map_or(false, |set| {
+                    set != node && is_whole_argument(&set, bind.as_deref(), &output_fn)
+                }) && key_name(node.clone()) == input_name
+            }
+            _ => false,
+        };
+        if is_reference {
+            references.push(node);
+        }
+    }
+    Ok(references)
+}
+
+/// an identifier node for `name`, fails if `name` isn't a valid identifier
+fn make_ident(name: &str) -> anyhow::Result<NixNode> {
+    parse_snippet(name, NODE_IDENT)
+        .ok()
+        .filter(|ident| ident.to_string() == name)
+        .ok_or_else(|| anyhow!("{} is not a valid input name", name))
+}
+
+/// replacements making the body of the outputs function
+/// refer to the input `old_name` as `new_ident`
+fn reference_replacements(
+    root: &NixNode,
+    old_name: &str,
+    new_ident: &NixNode,
+) -> anyhow::Result<Vec<(NixNode, GreenNode)>> {
+    input_references(root, old_name)?
+        .into_iter()
+        .map(|reference| {
+            let is_inherited = reference
+                .parent()
+                .map_or(false, |parent| parent.kind() == NODE_INHERIT);
+            if is_inherited {
+                return Err(AstError::UnsupportedSyntax(
+                    "inherit of a renamed input".to_string(),
+                    Span::of(&reference),
+                )
+                .into());
+            }
+            Ok((reference, new_ident.green().to_owned()))
+        })
+        .collect()
+}
+
+/// the nodes naming an input in the keys of the inputs attributes, along with the input name
+/// e.g. `nixpkgs` in `inputs.nixpkgs.url = "...";` or in `inputs = { nixpkgs.url = "..."; };`
+fn input_name_keys(root: &NixNode) -> Vec<(String, NixNode)> {
+    let top_level = match top_level_attrs(root) {
+        Some(top_level) => top_level.node().clone(),
+        None => return Vec::new(),
+    };
+    let mut result = Vec::new();
+    for (value, path, depth) in search_for_attr("inputs", 1, &top_level, None) {
+        if !path.starts_with(".inputs") {
+            continue;
+        }
+        let keys = match (depth, AttrSet::cast(value.clone())) {
+            // inputs = { nixpkgs.url = "..."; };
+            (1, Some(inputs)) => inputs
+                .entries()
+                .filter_map(|entry| entry.key()?.path().next())
+                .collect(),
+            // inputs.nixpkgs.url = "...";
+            _ => value
+                .parent()
+                .and_then(KeyValue::cast)
+                .and_then(|entry| entry.key()?.path().nth(1))
+                .into_iter()
+                .collect::<Vec<_>>(),
+        };
+        result.extend(keys.into_iter().map(|key| (key_name(key.clone()), key)));
+    }
+    result
+}
+
+/// renames an input everywhere at once: the keys of its attributes,
+/// the arguments and body of the outputs function, and the follows pointing to it
+pub fn rename_input(root: &NixNode, old_name: &str, new_name: &str) -> anyhow::Result<NixNode> {
+    if old_name == new_name {
+        return Ok(root.clone());
+    }
+    let new_ident = make_ident(new_name)?;
+    let keys = input_name_keys(root);
+    if keys.iter().any(|(name, _)| name == new_name) {
+        bail!("input {} already exists", new_name);
+    }
+    let mut replacements = keys
+        .into_iter()
+        .filter(|(name, _)| name == old_name)
+        .map(|(_, key)| (key, new_ident.green().to_owned()))
+        .collect::<Vec<_>>();
+    if replacements.is_empty() {
+        return Err(AstError::InputNotFound(old_name.to_string()).into());
+    }
+
+    if let Some(pattern) = get_output_fn(root)?.arg().and_then(Pattern::cast) {
+        replacements.extend(
+            pattern
+                .entries()
+                .filter_map(|entry| entry.name())
+                .filter(|name| name.as_str() == old_name)
+                .map(|name| (name.node().clone(), new_ident.green().to_owned())),
+        );
+    }
+    replacements.extend(reference_replacements(root, old_name, &new_ident)?);
+
+    // `inputs.foo.follows = "old";` and `inputs.foo.inputs.bar.follows = "old/bar";`
+    let follows = get_input_attrs(root, "follows", 3)
+        .into_iter()
+        .chain(get_input_attrs(root, "follows", 5));
+    for (_, node) in follows {
+        let target = match string_literal(&node) {
+            Some(target) => target,
+            None => continue,
+        };
+        let renamed = match target.strip_prefix(old_name) {
+            Some("") => new_name.to_string(),
+            Some(rest) if rest.starts_with('/') => format!("{}{}", new_name, rest),
+            _ => continue,
+        };
+        let new_string = parse_snippet(&to_nix_string(&renamed), NODE_STRING)?;
+        replacements.push((node, new_string.green().to_owned()));
+    }
+    Ok(replace_nodes(root, &replacements))
 }
 
 /// remove input node from outputs
 /// if it's listed
+/// fails if the body of outputs still uses the input,
+/// since there is nothing it could be rewritten to
 pub fn remove_input_from_output_fn(root: NixNode, input_name: &str) -> anyhow::Result<NixNode> {
-    let mut output_node = search_for_attr("outputs", 2, &root, None);
-    assert!(output_node.len() == 1);
-    let output_fn_node = Lambda::cast(output_node.remove(0).0).unwrap();
+    if let Some(reference) = input_references(&root, input_name)?.first() {
+        return Err(AstError::InputStillUsed(input_name.to_string(), Span::of(reference)).into());
+    }
+    let output_fn_node = get_output_fn(&root)?;
     if let Some(args) = output_fn_node.arg() {
         match args.kind() {
             NODE_IDENT => Ok(root),
             NODE_PATTERN => {
                 // TODO once rnix implements filter_entries, use that.
-                let fn_args = Pattern::cast(args.clone()).unwrap();
-                if fn_args.entries().next().is_none() {
+                if args.children().all(|child| child.kind() != NODE_PAT_ENTRY) {
                     return Ok(root);
                 }
 
@@ -214,11 +1038,15 @@ pub fn remove_input_from_output_fn(root: NixNode, input_name: &str) -> anyhow::R
 
                 for (idx, val) in args.children_with_tokens().enumerate() {
                     if let Some(pat) = val.as_node().and_then(|n| PatEntry::cast(n.clone())) {
-                        if pat.name().unwrap().as_str() != input_name {
+                        if pat.name().map_or(true, |name| name.as_str() != input_name) {
                             continue;
                         }
-                        if matching_arg_node.take().is_some() {
-                            panic!("argument {} found twice", input_name);
+                        if matching_arg_node.is_some() {
+                            return Err(AstError::DuplicateArgument(
+                                input_name.to_string(),
+                                Span::of(pat.node()),
+                            )
+                            .into());
                         }
                         matching_arg_node = Some((idx, pat));
                     } else if let Some((arg_node_idx, _)) = matching_arg_node {
@@ -235,21 +1063,48 @@ pub fn remove_input_from_output_fn(root: NixNode, input_name: &str) -> anyhow::R
                     }
                 }
 
-                let (arg_node_idx, arg_node) = matching_arg_node.unwrap();
+                let (arg_node_idx, arg_node) = match matching_arg_node {
+                    Some(matching_arg) => matching_arg,
+                    // the input isn't taken as an argument
+                    None => return Ok(root),
+                };
                 // unwrap or zero
                 let idx_end = match matching_comma {
                     Some(idx) => idx - arg_node_idx + 1,
                     None => 1,
                 };
-                kill_node_attribute(&arg_node.node(), idx_end)
+                // the last argument takes the comma before it along,
+                // nix doesn't allow a trailing comma
+                let previous_comma = args
+                    .children_with_tokens()
+                    .take(arg_node_idx)
+                    .enumerate()
+                    .filter(|(_, val)| !matches!(val.kind(), TOKEN_WHITESPACE | TOKEN_COMMENT))
+                    .last()
+                    .filter(|(_, val)| val.kind() == TOKEN_COMMA);
+                match (matching_comma, previous_comma) {
+                    (None, Some((comma_idx, _))) => Ok(splice_node_children(
+                        &args,
+                        comma_idx..arg_node_idx + 1,
+                        std::iter::empty(),
+                    )),
+                    _ => kill_node_attribute(&arg_node.node(), idx_end),
+                }
             }
-            _ => unimplemented!(),
+            _ => Err(AstError::UnsupportedSyntax(
+                "outputs function argument".to_string(),
+                Span::of(&args),
+            )
+            .into()),
         }
     } else {
         Ok(root)
     }
 }
 
+/// removes the input `dead_node_name` belongs to, as keyed by `get_inputs`,
+/// e.g. the whole of `nixpkgs = { type = "github"; ... };` for `.inputs.nixpkgs.type`,
+/// and its argument of the outputs
 pub fn remove_input(
     root: &NixNode,
     dead_node_name: &str,
@@ -263,14 +1118,548 @@ pub fn remove_input(
             &tmp
         }
     };
-    let dead_node = inputs.get(dead_node_name).unwrap();
-    let new_root = kill_node_attribute(&dead_node.parent().unwrap(), 1)
-        .map_err(|e| anyhow!("could not remove input: {}", e))?;
-    let input_name = get_attr(1, dead_node_name).unwrap();
-
-    remove_input_from_output_fn(new_root, &input_name)
+    let not_found = || AstError::InputNotFound(dead_node_name.to_string());
+    if !inputs.contains_key(dead_node_name) {
+        return Err(not_found().into());
+    }
+    let input_name = get_attr(1, dead_node_name).ok_or_else(not_found)?;
+    Flake::new(root.clone())
+        .remove_input(input_name)
+        .map(|flake| flake.root().clone())
 }
 
 pub fn get_attr(depth: usize, full_path: &str) -> Option<&str> {
     full_path.split('.').rev().nth(depth)
 }
+
+/// add input node to outputs
+/// if it's not already listed
+pub fn add_input_to_output_fn(root: NixNode, input_name: &str) -> anyhow::Result<NixNode> {
+    let output_fn_node = get_output_fn(&root)?;
+    let args = match output_fn_node.arg() {
+        Some(args) if args.kind() == NODE_PATTERN => args,
+        // `outputs = inputs: ...` takes every input already
+        _ => return Ok(root),
+    };
+    let fn_args = Pattern::cast(args.clone()).unwrap();
+    if fn_args
+        .entries()
+        .any(|pat| pat.name().map_or(false, |name| name.as_str() == input_name))
+    {
+        return Ok(root);
+    }
+
+    let new_arg = parse_snippet(&format!("{{ {} }}: null", input_name), NODE_PAT_ENTRY)?;
+    let children = args.children_with_tokens().collect::<Vec<_>>();
+    let last_arg = children
+        .iter()
+        .rposition(|child| child.kind() == NODE_PAT_ENTRY);
+    let (idx, new_children) = match last_arg {
+        // { self, nixpkgs } -> { self, nixpkgs, new_arg }
+        Some(idx) => (
+            idx + 1,
+            vec![
+                make_token(TOKEN_COMMA, ","),
+                make_token(TOKEN_WHITESPACE, " "),
+                NodeOrToken::Node(new_arg.green().to_owned()),
+            ],
+        ),
+        // { ... } -> { new_arg, ... } and {} -> { new_arg }
+        None => {
+            let open_idx = children
+                .iter()
+                .position(|child| child.kind() == TOKEN_CURLY_B_OPEN)
+                .ok_or_else(|| anyhow!("outputs function arguments are malformed"))?;
+            let idx = (open_idx + 1..children.len())
+                .find(|idx| children[*idx].kind() != TOKEN_WHITESPACE)
+                .ok_or_else(|| anyhow!("outputs function arguments are malformed"))?;
+            let mut new_children = vec![NodeOrToken::Node(new_arg.green().to_owned())];
+            if children[idx].kind() == TOKEN_ELLIPSIS {
+                new_children.push(make_token(TOKEN_COMMA, ","));
+                new_children.push(make_token(TOKEN_WHITESPACE, " "));
+            }
+            (idx, new_children)
+        }
+    };
+    Ok(splice_node_children(&args, idx..idx, new_children))
+}
+
+/// adds an input to the flake, using whichever style the flake already uses:
+/// `inputs = { name.url = "..."; };` if there is an inputs attribute set,
+/// `inputs.name.url = "...";` otherwise.
+/// The input is also added to the arguments of the outputs function.
+pub fn add_input(root: &NixNode, name: &str, url: &str, is_flake: bool) -> anyhow::Result<NixNode> {
+    let already_exists = get_inputs(root)
+        .keys()
+        .any(|path| get_attr(1, path) == Some(name));
+    if already_exists {
+        bail!("input {} already exists", name);
+    }
+
+    let root = add_input_to_output_fn(root.clone(), name)?;
+    let top_level =
+        top_level_attrs(&root).ok_or_else(|| anyhow!("flake is not an attribute set"))?;
+
+    let inputs_set = top_level.entries().find_map(|entry| {
+        (key_path(&entry) == ["inputs"])
+            .then(|| entry.value())
+            .flatten()
+            .and_then(AttrSet::cast)
+    });
+
+    let (attr_set, after, prefix) = match inputs_set {
+        Some(inputs_set) => (
+            inputs_set.node().clone(),
+            inputs_set
+                .entries()
+                .last()
+                .map(|entry| entry.node().clone()),
+            String::new(),
+        ),
+        None => {
+            let entries = top_level.entries().collect::<Vec<_>>();
+            let after = entries
+                .iter()
+                .rev()
+                .find(|entry| key_path(entry).first().map(String::as_str) == Some("inputs"))
+                .or_else(|| {
+                    entries
+                        .iter()
+                        .find(|entry| key_path(entry) == ["description"])
+                })
+                .map(|entry| entry.node().clone());
+            (top_level.node().clone(), after, "inputs.".to_string())
+        }
+    };
+
+    let mut new_entries = vec![parse_snippet(
+        &format!("{{ {}{}.url = {}; }}", prefix, name, to_nix_string(url)),
+        NODE_KEY_VALUE,
+    )?];
+    if !is_flake {
+        new_entries.push(parse_snippet(
+            &format!("{{ {}{}.flake = false; }}", prefix, name),
+            NODE_KEY_VALUE,
+        )?);
+    }
+    insert_entries(&attr_set, after.as_ref(), &new_entries)
+}
+
+/// replaces the entries saying where an input comes from, e.g. its `url` or its `type`,
+/// `owner` and `repo`, with `attrs`, pairs of attribute names and nix expressions
+/// the new entries take the place of the first old one, with the same attribute path prefix,
+/// e.g. `foo.url = "...";` becomes `foo.type = "..."; foo.owner = "...";`
+pub fn replace_input_ref(
+    root: &NixNode,
+    old_entries: &[NixNode],
+    attrs: &[(String, String)],
+) -> anyhow::Result<NixNode> {
+    let first = old_entries
+        .first()
+        .and_then(|entry| KeyValue::cast(entry.clone()))
+        .ok_or_else(|| AstError::InconsistentTree("input has no attributes".to_string()))?;
+    // the prefix is kept as written, quotes included
+    let mut prefix = first
+        .key()
+        .map(|key| key.path().map(|part| part.to_string()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    prefix.pop();
+    let new_entries = attrs
+        .iter()
+        .map(|(name, value)| {
+            let mut path = prefix.clone();
+            path.push(name.clone());
+            parse_snippet(
+                &format!("{{ {} = {}; }}", path.join("."), value),
+                NODE_KEY_VALUE,
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut parents: Vec<NixNode> = Vec::new();
+    for entry in old_entries {
+        let parent = entry
+            .parent()
+            .ok_or_else(|| AstError::InconsistentTree("attribute has no parent".to_string()))?;
+        if !parents.contains(&parent) {
+            parents.push(parent);
+        }
+    }
+    // the parents are rebuilt independently, so one can't hold another
+    if let Some(nested) = parents.iter().find(|parent| {
+        parent
+            .ancestors()
+            .skip(1)
+            .any(|ancestor| parents.contains(&ancestor))
+    }) {
+        return Err(AstError::UnsupportedSyntax(
+            "input attributes spread over nested attribute sets".to_string(),
+            Span::of(nested),
+        )
+        .into());
+    }
+    let separator = sibling_separator(first.node());
+    // the comments of the other entries move above the new ones
+    let moved_comments = old_entries
+        .iter()
+        .filter(|entry| *entry != first.node())
+        .flat_map(|entry| {
+            leading_comments(entry)
+                .into_iter()
+                .chain(trailing_comment(entry))
+        })
+        .collect::<Vec<_>>();
+    let replacements = parents
+        .iter()
+        .map(|parent| {
+            let mut children = Vec::new();
+            // removes the whitespace in front of a removed entry or comment
+            let pop_whitespace = |children: &mut Vec<NodeOrToken<GreenNode, GreenToken>>| {
+                if children.last().map_or(false, |last| {
+                    last.as_token().map(|tok| tok.kind())
+                        == Some(NixLanguage::kind_to_raw(TOKEN_WHITESPACE))
+                }) {
+                    children.pop();
+                }
+            };
+            for child in parent.children_with_tokens() {
+                match child {
+                    NodeOrToken::Node(node) if node == *first.node() => {
+                        for comment in &moved_comments {
+                            children.push(NodeOrToken::Token(comment.green().to_owned()));
+                            children.push(make_token(TOKEN_WHITESPACE, &separator));
+                        }
+                        for (idx, entry) in new_entries.iter().enumerate() {
+                            if idx > 0 {
+                                children.push(make_token(TOKEN_WHITESPACE, &separator));
+                            }
+                            children.push(NodeOrToken::Node(entry.green().to_owned()));
+                        }
+                    }
+                    NodeOrToken::Node(node) if old_entries.contains(&node) => {
+                        pop_whitespace(&mut children)
+                    }
+                    NodeOrToken::Token(tok) if moved_comments.contains(&tok) => {
+                        pop_whitespace(&mut children)
+                    }
+                    NodeOrToken::Node(node) => {
+                        children.push(NodeOrToken::Node(node.green().to_owned()))
+                    }
+                    NodeOrToken::Token(tok) => {
+                        children.push(NodeOrToken::Token(tok.green().to_owned()))
+                    }
+                }
+            }
+            (
+                parent.clone(),
+                GreenNode::new(NixLanguage::kind_to_raw(parent.kind()), children),
+            )
+        })
+        .collect::<Vec<_>>();
+    Ok(replace_nodes(root, &replacements))
+}
+
+/// replaces the url of an input, leaving everything around the
+/// string literal (comments, whitespace, follows, ...) untouched
+pub fn change_input_url(
+    root: &NixNode,
+    input_path: &str,
+    new_url: &str,
+    user_inputs: Option<&HashMap<String, NixNode>>,
+) -> anyhow::Result<NixNode> {
+    let tmp;
+    let inputs = match user_inputs {
+        Some(inputs) => inputs,
+        None => {
+            tmp = get_inputs(root);
+            &tmp
+        }
+    };
+    let url_node = inputs
+        .get(input_path)
+        .ok_or_else(|| AstError::InputNotFound(input_path.to_string()))?;
+    replace_string(url_node, new_url)
+}
+
+/// replaces a string node with a string literal holding `content`
+fn replace_string(node: &NixNode, content: &str) -> anyhow::Result<NixNode> {
+    let new_string = parse_snippet(&to_nix_string(content), NODE_STRING)?;
+    Ok(replace_node(node, new_string.green().to_owned()))
+}
+
+/// makes `nested_name`, an input of the input `input_name`, follow `target`
+/// e.g. `nixCargoIntegration.inputs.nixpkgs.follows = "nixpkgs";`
+/// the follows is placed right after the input's url, using the same attribute path
+pub fn add_follows(
+    root: &NixNode,
+    input_name: &str,
+    nested_name: &str,
+    target: &str,
+) -> anyhow::Result<NixNode> {
+    let follows_path = format!(".inputs.{}.inputs.{}.follows", input_name, nested_name);
+    if get_follows(root).contains_key(&follows_path) {
+        bail!("{} already follows another input", follows_path);
+    }
+    let inputs = get_inputs(root);
+    let url_entry = input_ref_node(&inputs, input_name)
+        .and_then(NixNode::parent)
+        .and_then(KeyValue::cast)
+        .ok_or_else(|| AstError::InputNotFound(input_name.to_string()))?;
+
+    // `url`, `foo.url` or `inputs.foo.url`, depending on where the url is,
+    // or the type for inputs written as attribute sets
+    let mut path = key_path(&url_entry);
+    path.pop();
+    path.extend(vec![
+        "inputs".to_string(),
+        nested_name.to_string(),
+        "follows".to_string(),
+    ]);
+    let new_entry = parse_snippet(
+        &format!("{{ {} = {}; }}", path.join("."), to_nix_string(target)),
+        NODE_KEY_VALUE,
+    )?;
+    let attr_set = url_entry
+        .node()
+        .parent()
+        .ok_or_else(|| anyhow!("input {} has no parent", input_name))?;
+    insert_entries(&attr_set, Some(url_entry.node()), &[new_entry])
+}
+
+/// removes a follows, given its path as returned by `get_follows`
+pub fn remove_follows(root: &NixNode, follows_path: &str) -> anyhow::Result<NixNode> {
+    let follows = get_follows(root);
+    let dead_entry = follows
+        .get(follows_path)
+        .and_then(NixNode::parent)
+        .ok_or_else(|| AstError::FollowsNotFound(follows_path.to_string()))?;
+    kill_node_attribute(&dead_entry, 1)
+}
+
+/// changes the input followed, given the follows path as returned by `get_follows`
+pub fn retarget_follows(
+    root: &NixNode,
+    follows_path: &str,
+    target: &str,
+) -> anyhow::Result<NixNode> {
+    let follows = get_follows(root);
+    let node = follows
+        .get(follows_path)
+        .ok_or_else(|| AstError::FollowsNotFound(follows_path.to_string()))?;
+    replace_string(node, target)
+}
+
+/// makes every flake input follow `target` for its own input of the same name
+/// so only one copy of e.g. nixpkgs ends up in the lock file
+/// `only` restricts it to the inputs known to have such an input
+pub fn make_inputs_follow(
+    root: &NixNode,
+    target: &str,
+    only: Option<&[String]>,
+) -> anyhow::Result<NixNode> {
+    let inputs = get_inputs(root);
+    if input_ref_node(&inputs, target).is_none() {
+        return Err(AstError::InputNotFound(target.to_string()).into());
+    }
+    let non_flakes = get_non_flake_inputs(root);
+    let mut names = inputs
+        .keys()
+        .filter_map(|path| get_attr(1, path))
+        .filter(|name| *name != target && !non_flakes.iter().any(|n| n == name))
+        .filter(|name| only.map_or(true, |only| only.iter().any(|n| n == name)))
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+
+    let mut root = root.clone();
+    for name in names {
+        let follows_path = format!(".inputs.{}.inputs.{}.follows", name, target);
+        root = match get_follows(&root).get(&follows_path) {
+            Some(node) if node.to_string() == to_nix_string(target) => continue,
+            Some(node) => replace_string(node, target)?,
+            None => add_follows(&root, name, target, target)?,
+        };
+    }
+    Ok(root)
+}
+
+/// functions building a derivation whose dependencies can be managed
+const DERIVATION_BUILDERS: [&str; 3] = ["mkDerivation", "buildRustPackage", "mkShell"];
+
+/// the name a function is called by, e.g. `mkDerivation` for `pkgs.stdenv.mkDerivation`
+fn function_name(node: &NixNode) -> Option<String> {
+    match node.kind() {
+        NODE_IDENT => Some(Ident::cast(node.clone())?.as_str().to_string()),
+        NODE_SELECT => function_name(&Select::cast(node.clone())?.index()?),
+        _ => None,
+    }
+}
+
+/// the calls to a derivation builder in the body of the outputs function,
+/// as `(name, builder, attribute set given to the builder)`
+/// the name is the path of the attributes and `let` bindings the call is in,
+/// e.g. `devShell` for `devShell = pkgs.mkShell { ... };`,
+/// `outputs` if there is none, and numbered if several calls have the same one
+pub fn derivation_calls(root: &NixNode) -> anyhow::Result<Vec<(String, String, NixNode)>> {
+    let body = get_output_fn(root)?
+        .body()
+        .ok_or(AstError::MissingOutputs)?;
+    let mut calls: Vec<(String, String, NixNode)> = Vec::new();
+    for apply in body.descendants().filter_map(Apply::cast) {
+        let builder = match apply.lambda().as_ref().and_then(function_name) {
+            Some(builder) if DERIVATION_BUILDERS.contains(&builder.as_str()) => builder,
+            _ => continue,
+        };
+        let attrs = match apply.value() {
+            Some(attrs) if attrs.kind() == NODE_ATTR_SET => attrs,
+            _ => continue,
+        };
+        let mut path = apply
+            .node()
+            .ancestors()
+            .take_while(|ancestor| *ancestor != body)
+            .filter_map(KeyValue::cast)
+            .map(|entry| key_path(&entry).join("."))
+            .collect::<Vec<_>>();
+        path.reverse();
+        let mut name = path.join(".").split_whitespace().collect::<String>();
+        if name.is_empty() {
+            name = "outputs".to_string();
+        }
+        let same_name = calls
+            .iter()
+            .filter(|(other, _, _)| other.split('#').next() == Some(name.as_str()))
+            .count();
+        if same_name > 0 {
+            name = format!("{}#{}", name, same_name + 1);
+        }
+        calls.push((name, builder, attrs));
+    }
+    Ok(calls)
+}
+
+/// the list the attribute `attr` of a derivation's attributes is set to,
+/// and whether it is in the scope of `with pkgs;`
+/// e.g. `buildInputs = [ pkgs.openssl ];` or `buildInputs = with pkgs; [ openssl ];`
+fn dependency_list(attrs: &NixNode, attr: &str) -> anyhow::Result<Option<(NixNode, bool)>> {
+    let value = match AttrSet::cast(attrs.clone())
+        .and_then(|attrs| attrs.entries().find(|entry| key_path(entry) == [attr]))
+        .and_then(|entry| entry.value())
+    {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let (list, in_pkgs_scope) = match With::cast(value.clone()) {
+        Some(with) => (
+            with.body(),
+            with.namespace()
+                .map_or(false, |namespace| namespace.to_string() == "pkgs"),
+        ),
+        None => (Some(value.clone()), false),
+    };
+    match list.filter(|list| list.kind() == NODE_LIST) {
+        Some(list) => Ok(Some((list, in_pkgs_scope))),
+        None => Err(AstError::UnsupportedSyntax(
+            format!("{} that isn't a list", attr),
+            Span::of(&value),
+        )
+        .into()),
+    }
+}
+
+/// a dependency as it is written in a list, on a single line
+fn dependency_text(node: &NixNode) -> String {
+    node.to_string()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the dependencies in the list the attribute `attr` of a derivation's attributes is set to
+pub fn list_dependencies(attrs: &NixNode, attr: &str) -> Vec<String> {
+    match dependency_list(attrs, attr) {
+        Ok(Some((list, _))) => list.children().map(|node| dependency_text(&node)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// adds the package `package` of nixpkgs to the list of the attribute `attr`
+/// of a derivation's attributes, creating it if it doesn't exist
+/// written `pkgs.<package>`, or just `<package>` in the scope of `with pkgs;`
+pub fn add_dependency(attrs: &NixNode, attr: &str, package: &str) -> anyhow::Result<NixNode> {
+    let dependency = |in_pkgs_scope: bool| -> anyhow::Result<NixNode> {
+        let text = if in_pkgs_scope {
+            package.to_string()
+        } else {
+            format!("pkgs.{}", package)
+        };
+        string_to_node(text.clone())
+            .ok()
+            .filter(|node| {
+                matches!(node.kind(), NODE_IDENT | NODE_SELECT) && node.to_string() == text
+            })
+            .ok_or_else(|| anyhow!(format!("{} is not a valid package name", package)))
+    };
+    match dependency_list(attrs, attr)? {
+        Some((list, in_pkgs_scope)) => {
+            let dependency = dependency(in_pkgs_scope)?;
+            if list
+                .children()
+                .any(|node| dependency_text(&node) == dependency.to_string())
+            {
+                bail!("{} is already in {}", dependency, attr);
+            }
+            let last = match list.children().last() {
+                Some(last) => last,
+                None => {
+                    let new_list = parse_snippet(&format!("[ {} ]", dependency), NODE_LIST)?;
+                    return Ok(replace_node(&list, new_list.green().to_owned()));
+                }
+            };
+            // after the comment on the same line as the last dependency, if any
+            let after = trailing_comment(&last)
+                .map_or_else(|| NodeOrToken::Node(last.clone()), NodeOrToken::Token);
+            let idx = list
+                .children_with_tokens()
+                .position(|child| child == after)
+                .ok_or_else(|| {
+                    AstError::InconsistentTree("child not in parent tree".to_string())
+                })?
+                + 1;
+            Ok(splice_node_children(
+                &list,
+                idx..idx,
+                vec![
+                    make_token(TOKEN_WHITESPACE, &sibling_separator(&last)),
+                    NodeOrToken::Node(dependency.green().to_owned()),
+                ],
+            ))
+        }
+        None => {
+            let dependency = dependency(false)?;
+            let new_entry = parse_snippet(
+                &format!("{{ {} = [ {} ]; }}", attr, dependency),
+                NODE_KEY_VALUE,
+            )?;
+            let last_entry = AttrSet::cast(attrs.clone())
+                .and_then(|attrs| attrs.entries().last())
+                .map(|entry| entry.node().clone());
+            insert_entries(attrs, last_entry.as_ref(), &[new_entry])
+        }
+    }
+}
+
+/// removes `dependency`, as written in the list of the attribute `attr`
+/// of a derivation's attributes, leaving an empty list if it was the only one
+pub fn remove_dependency(attrs: &NixNode, attr: &str, dependency: &str) -> anyhow::Result<NixNode> {
+    let (list, _) = dependency_list(attrs, attr)?
+        .ok_or_else(|| AstError::DependencyNotFound(dependency.to_string()))?;
+    let dead = list
+        .children()
+        .find(|node| dependency_text(node) == dependency)
+        .ok_or_else(|| AstError::DependencyNotFound(dependency.to_string()))?;
+    if list.children().count() == 1 {
+        let new_list = parse_snippet("[ ]", NODE_LIST)?;
+        return Ok(replace_node(&list, new_list.green().to_owned()));
+    }
+    kill_node_attribute(&dead, 1)
+}
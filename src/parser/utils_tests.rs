@@ -1,5 +1,8 @@
+use crate::parser::error::{AstError, Span};
 use crate::parser::utils::{
-    get_inputs, get_output_node, node_to_string, remove_input, string_to_node,
+    add_follows, add_input, change_input_url, get_follows, get_inputs, get_output_node,
+    input_references, make_inputs_follow, node_to_string, remove_follows, remove_input,
+    rename_input, retarget_follows, string_to_node,
 };
 
 use rnix::{types::*, SyntaxKind::*};
@@ -95,3 +98,321 @@ pub fn remove_inputs_one_arg_no_ellipsis() {
     assert!(args.contains('}'));
     assert!(!args.contains("..."));
 }
+
+fn output_fn_args(root: &crate::parser::utils::NixNode) -> Vec<String> {
+    let args = get_output_node(root).unwrap().arg().unwrap();
+    Pattern::cast(args)
+        .unwrap()
+        .entries()
+        .map(|entry| entry.name().unwrap().as_str().to_string())
+        .collect()
+}
+
+#[test]
+pub fn add_input_nested_inputs() {
+    let ast = string_to_node(include_str!("../../test_data/inputs.nix").to_string()).unwrap();
+    let result = add_input(&ast, "utils", "github:numtide/flake-utils", true).unwrap();
+    let new_inputs = get_inputs(&result);
+    let utils = new_inputs.get(".inputs.utils.url").unwrap().clone();
+    assert_eq!(node_to_string(utils), "github:numtide/flake-utils");
+    assert_eq!(new_inputs.len(), 5);
+    assert!(result.to_string().contains(
+        "      inputs.nixpkgs.follows = \"nixpkgs\";\n    };\n    utils.url = \"github:numtide/flake-utils\";\n  };"
+    ));
+    assert_eq!(output_fn_args(&result), vec!["utils"]);
+    assert!(get_output_node(&result)
+        .unwrap()
+        .arg()
+        .unwrap()
+        .to_string()
+        .contains("{utils, ...}"));
+}
+
+#[test]
+pub fn add_input_dotted_inputs() {
+    let ast = string_to_node(include_str!("../../test_data/multi_arg.nix").to_string()).unwrap();
+    let result = add_input(&ast, "src", "github:foo/bar", false).unwrap();
+    assert!(result.to_string().contains(
+        "    url = \"hello_world\";\n  };\n  inputs.src.url = \"github:foo/bar\";\n  inputs.src.flake = false;\n\n  outputs"
+    ));
+    let new_inputs = get_inputs(&result);
+    let src = new_inputs.get(".inputs.src.url").unwrap().clone();
+    assert_eq!(node_to_string(src), "github:foo/bar");
+    assert_eq!(output_fn_args(&result), vec!["hello", "another_one", "src"]);
+    assert!(add_input(&result, "src", "github:foo/bar", false).is_err());
+}
+
+#[test]
+pub fn add_input_no_inputs() {
+    let ast = string_to_node(include_str!("../../test_data/zero_args.nix").to_string()).unwrap();
+    let result = add_input(&ast, "hello", "abc", true).unwrap();
+    assert_eq!(
+        result.to_string(),
+        "{\n  inputs.hello.url = \"abc\";\n  outputs = inputs@{hello}: {};\n}"
+    );
+
+    let ast = string_to_node(include_str!("../../test_data/no_args.nix").to_string()).unwrap();
+    let result = add_input(&ast, "hello", "abc", true).unwrap();
+    assert_eq!(
+        result.to_string(),
+        "{\n  inputs.hello.url = \"abc\";\n  outputs = inputs: {};\n}"
+    );
+}
+
+#[test]
+pub fn change_input_url_in_place() {
+    let source = include_str!("../../test_data/inputs.nix");
+    let ast = string_to_node(source.to_string()).unwrap();
+    let result = change_input_url(
+        &ast,
+        ".inputs.nixCargoIntegration.url",
+        "github:yusdacra/nix-cargo-integration/release",
+        None,
+    )
+    .unwrap();
+    let new_inputs = get_inputs(&result);
+    let nix_cargo_integration = new_inputs
+        .get(".inputs.nixCargoIntegration.url")
+        .unwrap()
+        .clone();
+    assert_eq!(
+        node_to_string(nix_cargo_integration),
+        "github:yusdacra/nix-cargo-integration/release"
+    );
+    assert_eq!(
+        result.to_string(),
+        source.trim_end().replace(
+            "\"github:yusdacra/nix-cargo-integration\"",
+            "\"github:yusdacra/nix-cargo-integration/release\""
+        )
+    );
+
+    let result = change_input_url(&result, ".inputs.hello.url", "\"${quoted}\"", None).unwrap();
+    let hello = get_inputs(&result)
+        .get(".inputs.hello.url")
+        .unwrap()
+        .clone();
+    assert_eq!(node_to_string(hello), "\"${quoted}\"");
+    assert!(change_input_url(&result, ".inputs.missing.url", "abc", None).is_err());
+}
+
+#[test]
+pub fn check_follows() {
+    let ast = string_to_node(include_str!("../../test_data/inputs.nix").to_string()).unwrap();
+    let follows = get_follows(&ast);
+    assert_eq!(follows.len(), 1);
+    let nixpkgs = follows
+        .get(".inputs.nixCargoIntegration.inputs.nixpkgs.follows")
+        .unwrap()
+        .clone();
+    assert_eq!(node_to_string(nixpkgs), "nixpkgs");
+}
+
+#[test]
+pub fn add_retarget_remove_follows() {
+    let ast = string_to_node(include_str!("../../test_data/inputs.nix").to_string()).unwrap();
+    let result = add_follows(&ast, "hello", "nixpkgs", "nixpkgs").unwrap();
+    let result = add_follows(&result, "another_one", "utils", "hello").unwrap();
+    assert!(result.to_string().contains(
+        "inputs.hello.url = \"abc\";\n  inputs.hello.inputs.nixpkgs.follows = \"nixpkgs\";"
+    ));
+    assert!(result
+        .to_string()
+        .contains("url = \"hello_world\";\n    inputs.utils.follows = \"hello\";"));
+    assert_eq!(get_follows(&result).len(), 3);
+    assert!(add_follows(&result, "hello", "nixpkgs", "nixpkgs").is_err());
+
+    let result = retarget_follows(
+        &result,
+        ".inputs.another_one.inputs.utils.follows",
+        "nixpkgs",
+    )
+    .unwrap();
+    let utils = get_follows(&result)
+        .get(".inputs.another_one.inputs.utils.follows")
+        .unwrap()
+        .clone();
+    assert_eq!(node_to_string(utils), "nixpkgs");
+
+    let result = remove_follows(
+        &result,
+        ".inputs.nixCargoIntegration.inputs.nixpkgs.follows",
+    )
+    .unwrap();
+    let follows = get_follows(&result);
+    assert_eq!(follows.len(), 2);
+    assert!(!follows.contains_key(".inputs.nixCargoIntegration.inputs.nixpkgs.follows"));
+    assert_eq!(get_inputs(&result).len(), 4);
+}
+
+#[test]
+pub fn follow_nixpkgs_everywhere() {
+    let ast = string_to_node(include_str!("../../test_data/inputs.nix").to_string()).unwrap();
+    let result = add_input(&ast, "src", "github:foo/bar", false).unwrap();
+    let result = add_follows(&result, "hello", "nixpkgs", "another_one").unwrap();
+    let result = make_inputs_follow(&result, "nixpkgs", None).unwrap();
+    let follows = get_follows(&result);
+    let mut follows_paths = follows.keys().cloned().collect::<Vec<_>>();
+    follows_paths.sort();
+    assert_eq!(
+        follows_paths,
+        vec![
+            ".inputs.another_one.inputs.nixpkgs.follows",
+            ".inputs.hello.inputs.nixpkgs.follows",
+            ".inputs.nixCargoIntegration.inputs.nixpkgs.follows",
+        ]
+    );
+    assert!(follows
+        .values()
+        .all(|node| node_to_string(node.clone()) == "nixpkgs"));
+    assert!(make_inputs_follow(&result, "missing", None).is_err());
+}
+
+#[test]
+pub fn structured_errors() {
+    let ast = string_to_node("{ inputs.hello.url = \"abc\"; }".to_string()).unwrap();
+    let err = remove_input(&ast, ".inputs.hello.url", None).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<AstError>(),
+        Some(&AstError::MissingOutputs)
+    );
+
+    let ast =
+        string_to_node("{\n  inputs.hello.url = \"abc\";\n  outputs = 1;\n}".to_string()).unwrap();
+    let err = add_input(&ast, "foo", "bar", true).unwrap_err();
+    match err.downcast_ref::<AstError>() {
+        Some(AstError::OutputsNotLambda(span)) => {
+            assert_eq!((span.start, span.end), (42, 43));
+            assert_eq!((span.line, span.column), (3, 13));
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+    assert_eq!(
+        err.to_string(),
+        "outputs is not a function: `1` at line 3, column 13"
+    );
+    let err = remove_input(&ast, ".inputs.missing.url", None).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<AstError>(),
+        Some(&AstError::InputNotFound(".inputs.missing.url".to_string()))
+    );
+}
+
+#[test]
+pub fn quoted_input_names() {
+    let ast = string_to_node(
+        "{\n  inputs.\"nixpkgs-unstable\".url = \"github:NixOS/nixpkgs\";\n  outputs = inputs: {};\n}"
+            .to_string(),
+    )
+    .unwrap();
+    let inputs = get_inputs(&ast);
+    assert!(inputs.contains_key(".inputs.nixpkgs-unstable.url"));
+    let result = remove_input(&ast, ".inputs.nixpkgs-unstable.url", None).unwrap();
+    assert!(get_inputs(&result).is_empty());
+}
+
+#[test]
+pub fn let_bound_outputs() {
+    let ast = string_to_node(include_str!("../../test_data/let_outputs.nix").to_string()).unwrap();
+    assert_eq!(get_inputs(&ast).len(), 3);
+    assert_eq!(output_fn_args(&ast), vec!["self", "hello"]);
+    // `c` shadows hello
+    let hello = input_references(&ast, "hello").unwrap();
+    assert_eq!(hello.len(), 1);
+    assert_eq!(Span::of(&hello[0]).line, 3);
+    assert_eq!(input_references(&ast, "another_one").unwrap().len(), 1);
+    assert!(input_references(&ast, "unused").unwrap().is_empty());
+
+    let err = remove_input(&ast, ".inputs.hello.url", None).unwrap_err();
+    match err.downcast_ref::<AstError>() {
+        Some(AstError::InputStillUsed(name, span)) => {
+            assert_eq!(name, "hello");
+            assert_eq!((span.line, span.column), (3, 9));
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+    let result = remove_input(&ast, ".inputs.unused.url", None).unwrap();
+    assert_eq!(get_inputs(&result).len(), 2);
+
+    let result = add_input(&result, "utils", "github:numtide/flake-utils", true).unwrap();
+    assert_eq!(output_fn_args(&result), vec!["self", "hello", "utils"]);
+    assert!(result
+        .to_string()
+        .contains("inputs.another_one.url = \"hello_world\";\n  inputs.utils.url"));
+
+    let result = rename_input(&result, "hello", "greeting").unwrap();
+    assert!(result.to_string().contains("a = greeting;"));
+    assert!(result.to_string().contains("c = let hello = 1; in hello;"));
+    assert!(result
+        .to_string()
+        .contains("inputs.greeting.url = \"abc\";"));
+    assert_eq!(output_fn_args(&result), vec!["self", "greeting", "utils"]);
+}
+
+#[test]
+pub fn ident_outputs_arg() {
+    let ast = string_to_node(include_str!("../../test_data/ident_arg.nix").to_string()).unwrap();
+    // the `inputs` of `c` is another one
+    assert_eq!(input_references(&ast, "hello").unwrap().len(), 1);
+    assert_eq!(input_references(&ast, "another_one").unwrap().len(), 1);
+    assert!(remove_input(&ast, ".inputs.another_one.url", None).is_err());
+
+    let result = rename_input(&ast, "another_one", "other").unwrap();
+    assert!(result.to_string().contains("b = inputs ? other;"));
+    let result = rename_input(&result, "hello", "greeting").unwrap();
+    assert!(result.to_string().contains("a = inputs.greeting;"));
+    assert!(result
+        .to_string()
+        .contains("c = { inputs, ... }: inputs.hello;"));
+    assert!(input_references(&result, "hello").unwrap().is_empty());
+}
+
+#[test]
+pub fn rename_input_everywhere() {
+    let ast = string_to_node(include_str!("../../test_data/inputs.nix").to_string()).unwrap();
+    let result = add_follows(&ast, "hello", "nixpkgs", "nixpkgs/nixpkgs").unwrap();
+    let result = rename_input(&result, "nixpkgs", "unstable").unwrap();
+    let inputs = get_inputs(&result);
+    assert!(inputs.contains_key(".inputs.unstable.url"));
+    assert!(!inputs.contains_key(".inputs.nixpkgs.url"));
+    let follows = get_follows(&result);
+    // the name of the input's own input stays the same
+    let nix_cargo_integration = follows
+        .get(".inputs.nixCargoIntegration.inputs.nixpkgs.follows")
+        .unwrap()
+        .clone();
+    assert_eq!(node_to_string(nix_cargo_integration), "unstable");
+    let hello = follows
+        .get(".inputs.hello.inputs.nixpkgs.follows")
+        .unwrap()
+        .clone();
+    assert_eq!(node_to_string(hello), "unstable/nixpkgs");
+
+    assert!(rename_input(&result, "missing", "foo").is_err());
+    assert!(rename_input(&result, "hello", "unstable").is_err());
+    assert!(rename_input(&result, "hello", "not valid").is_err());
+}
+
+#[test]
+pub fn remove_attrs_inputs() {
+    let ast = string_to_node(include_str!("../../test_data/attrs_inputs.nix").to_string()).unwrap();
+    let inputs = get_inputs(&ast);
+    assert!(inputs.contains_key(".inputs.nixpkgs.type"));
+    assert!(inputs.contains_key(".inputs.private.type"));
+    let result = remove_input(&ast, ".inputs.nixpkgs.type", None).unwrap();
+    let result = remove_input(&result, ".inputs.private.type", None).unwrap();
+    assert_eq!(
+        result.to_string(),
+        r#"{
+  inputs = {
+    nixCargoIntegration = {
+      # pinned until the next release
+      url = "github:yusdacra/nix-cargo-integration?dir=lib";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+  };
+
+  outputs = { self, nixCargoIntegration }: {};
+}"#
+    );
+}
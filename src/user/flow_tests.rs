@@ -0,0 +1,415 @@
+use crate::parser::lock::FlakeLock;
+use crate::run_interactive;
+use crate::user::script::{Answer, ScriptedPrompter};
+use crate::user::UserMetadata;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// a directory of its own for a test, holding a copy of `fixture` as flake.nix,
+/// and of `lock` as flake.lock if given
+fn flake_dir(test: &str, fixture: &str, lock: Option<&str>) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("flake_generator_{}_{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("flake.nix"), fixture).unwrap();
+    if let Some(lock) = lock {
+        fs::write(dir.join("flake.lock"), lock).unwrap();
+    }
+    dir
+}
+
+fn select(items: &[&'static str]) -> Answer {
+    Answer::Select(items.to_vec())
+}
+
+/// runs the interactive interface with `answers`, which must end with exiting
+fn run(answers: Vec<Answer>) -> (ScriptedPrompter, UserMetadata) {
+    run_with(UserMetadata::default(), answers)
+}
+
+fn run_with(user_data: UserMetadata, answers: Vec<Answer>) -> (ScriptedPrompter, UserMetadata) {
+    let mut prompter = ScriptedPrompter::new(answers);
+    let user_data = run_interactive(&mut prompter, user_data);
+    (prompter, user_data)
+}
+
+#[test]
+pub fn modify_remove_input_and_write() {
+    let dir = flake_dir(
+        "remove_input",
+        include_str!("../../test_data/inputs.nix"),
+        Some(include_str!("../../test_data/inputs.lock")),
+    );
+    let flake = dir.join("flake.nix");
+    let (prompter, _) = run(vec![
+        select(&["modify"]),
+        Answer::Type(flake.to_string_lossy().to_string()),
+        select(&["delete input"]),
+        select(&["hello"]),
+        select(&["review changes"]),
+        select(&["write changes"]),
+        select(&["back"]),
+        select(&["back"]),
+        select(&["back"]),
+        Answer::Type("back".to_string()),
+        select(&["exit"]),
+    ]);
+
+    let removal = &prompter.steps[3];
+    assert!(removal.items[0].starts_with("nixpkgs "));
+    // inputs only in flake.lock can't be removed from the flake
+    assert_eq!(
+        removal
+            .items
+            .iter()
+            .map(|item| item.split_whitespace().next().unwrap())
+            .collect::<Vec<_>>(),
+        vec![
+            "nixpkgs",
+            "nixCargoIntegration",
+            "hello",
+            "another_one",
+            "back"
+        ]
+    );
+    let preview = removal.preview.as_deref().unwrap();
+    assert!(preview.contains("-  inputs.hello.url = \"abc\";"));
+    let review = &prompter.steps[5].header;
+    assert!(review.starts_with("Unsaved changes to the flake:"));
+    assert!(review.contains(preview.lines().last().unwrap()));
+
+    assert_eq!(
+        fs::read_to_string(&flake).unwrap(),
+        r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixpkgs-unstable";
+    nixCargoIntegration = {
+      url = "github:yusdacra/nix-cargo-integration";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+  };
+
+  inputs.another_one = {
+    url = "hello_world";
+  };
+
+  outputs = inputs@{ ... }: { };
+}
+"#
+    );
+    let lock = FlakeLock::read_for(&flake.to_string_lossy())
+        .unwrap()
+        .unwrap();
+    assert!(lock.input("hello").is_none());
+    assert!(lock.input("nixpkgs").is_some());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn remove_several_inputs_at_once() {
+    let dir = flake_dir(
+        "remove_inputs",
+        include_str!("../../test_data/inputs.nix"),
+        None,
+    );
+    let flake = dir.join("flake.nix");
+    let (prompter, user_data) = run(vec![
+        select(&["modify"]),
+        Answer::Type(flake.to_string_lossy().to_string()),
+        select(&["delete input"]),
+        select(&["hello", "another_one"]),
+        select(&["undo last edit"]),
+        select(&["redo"]),
+        select(&["review changes"]),
+        select(&["discard changes"]),
+        select(&["back"]),
+        select(&["back"]),
+        select(&["back"]),
+        Answer::Type("back".to_string()),
+        select(&["exit"]),
+    ]);
+
+    // both are removed by a single edit, which a single undo takes back
+    let after_undo = &prompter.steps[5].items;
+    assert!(after_undo.contains(&"redo".to_string()));
+    assert!(!after_undo.contains(&"undo last edit".to_string()));
+    let review = &prompter.steps[7].header;
+    assert!(review.contains("-  inputs.hello.url = \"abc\";"));
+    assert!(review.contains("-  inputs.another_one = {"));
+
+    let names = user_data
+        .flake()
+        .inputs()
+        .iter()
+        .map(|input| input.name().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec!["nixpkgs", "nixCargoIntegration", "hello", "another_one"]
+    );
+    assert_eq!(
+        fs::read_to_string(&flake).unwrap(),
+        include_str!("../../test_data/inputs.nix")
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn rust_generate_flow() {
+    // generating is left out, since rust-nix-templater writes to the working directory
+    let (prompter, user_data) = run(vec![
+        select(&["create"]),
+        select(&["rust"]),
+        select(&["set package name"]),
+        Answer::Type("hello".to_string()),
+        select(&["toggle github actions", "toggle library flag"]),
+        select(&["set license"]),
+        select(&["MIT license"]),
+        select(&["back"]),
+        select(&["back"]),
+        select(&["exit"]),
+    ]);
+
+    let options = &user_data.rust_options;
+    assert_eq!(options.package_name.as_deref(), Some("hello"));
+    assert_eq!(options.package_license.as_deref(), Some("MIT"));
+    assert!(options.github_ci);
+    assert!(options.package_lib);
+    assert!(!options.gitlab_ci);
+
+    let toggles = &prompter.steps[4];
+    assert!(toggles
+        .items
+        .contains(&"set package name (hello)".to_string()));
+    assert!(toggles
+        .preview
+        .as_deref()
+        .unwrap()
+        .contains("github actions: yes"));
+    let licenses = &prompter.steps[6];
+    assert_eq!(licenses.header, "Choose or type a license.");
+    assert!(licenses
+        .preview
+        .as_deref()
+        .unwrap()
+        .contains("license: MIT"));
+    assert!(prompter.steps[7]
+        .items
+        .contains(&"toggle library flag (true)".to_string()));
+}
+
+#[test]
+pub fn write_changes_keeping_a_backup() {
+    let dir = flake_dir(
+        "backup",
+        include_str!("../../test_data/inputs.nix"),
+        Some(include_str!("../../test_data/inputs.lock")),
+    );
+    let flake = dir.join("flake.nix");
+    let (prompter, _) = run(vec![
+        select(&["modify"]),
+        Answer::Type(flake.to_string_lossy().to_string()),
+        select(&["delete input"]),
+        select(&["hello"]),
+        select(&["review changes"]),
+        select(&["write changes, keeping a backup"]),
+        select(&["back"]),
+        select(&["back"]),
+        select(&["back"]),
+        Answer::Type("back".to_string()),
+        select(&["exit"]),
+    ]);
+
+    assert!(prompter.steps[5]
+        .items
+        .contains(&"write changes, keeping a backup".to_string()));
+    assert_eq!(
+        fs::read_to_string(dir.join("flake.nix.bak")).unwrap(),
+        include_str!("../../test_data/inputs.nix")
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("flake.lock.bak")).unwrap(),
+        include_str!("../../test_data/inputs.lock")
+    );
+    assert!(!fs::read_to_string(&flake)
+        .unwrap()
+        .contains("inputs.hello.url"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn yes_is_only_an_answer_to_whether_an_input_is_a_flake() {
+    let dir = flake_dir("yes_no", include_str!("../../test_data/inputs.nix"), None);
+    let flake = dir.join("flake.nix");
+    let (_, user_data) = run(vec![
+        select(&["modify"]),
+        Answer::Type(flake.to_string_lossy().to_string()),
+        select(&["add input"]),
+        Answer::Type("utils github:numtide/flake-utils".to_string()),
+        select(&["yes"]),
+        select(&["rename input"]),
+        select(&["hello"]),
+        Answer::Type("yes".to_string()),
+        select(&["review changes"]),
+        select(&["write changes"]),
+        select(&["back"]),
+        Answer::Type("back".to_string()),
+        select(&["back"]),
+        select(&["back"]),
+        select(&["back"]),
+        Answer::Type("back".to_string()),
+        select(&["back"]),
+        Answer::Type("back".to_string()),
+        select(&["exit"]),
+    ]);
+
+    assert!(user_data.pending_input.is_none());
+    let written = fs::read_to_string(&flake).unwrap();
+    assert!(written.contains("inputs.yes.url = \"abc\";"));
+    assert_eq!(written.matches("github:numtide/flake-utils").count(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn packages_named_like_prompts_are_packages() {
+    let dir = flake_dir(
+        "package_items",
+        include_str!("../../test_data/build_inputs.nix"),
+        None,
+    );
+    let flake = dir.join("flake.nix");
+    let index = dir.join("packages.json");
+    fs::write(
+        &index,
+        r#"{
+  "legacyPackages.x86_64-linux.back": {},
+  "legacyPackages.x86_64-linux.licensee": {
+    "description": "Detects under what license a project is distributed",
+    "version": "9.15.1"
+  },
+  "legacyPackages.x86_64-linux.rust": {}
+}"#,
+    )
+    .unwrap();
+    let (prompter, _) = run_with(
+        UserMetadata::with_packages_file(Some(index.to_string_lossy().to_string())),
+        vec![
+            select(&["modify"]),
+            Answer::Type(flake.to_string_lossy().to_string()),
+            select(&["add dependency"]),
+            select(&["devShell.x86_64-linux packages"]),
+            select(&["back"]),
+            select(&["add dependency"]),
+            select(&["devShell.x86_64-linux packages"]),
+            select(&["licensee"]),
+            select(&["add dependency"]),
+            select(&["devShell.x86_64-linux packages"]),
+            select(&["rust"]),
+            select(&["review changes"]),
+            select(&["discard changes"]),
+            // typing exit works anywhere, the packages have no item to go back
+            Answer::Type("exit".to_string()),
+        ],
+    );
+
+    assert_eq!(
+        prompter.steps[7].items[1],
+        "licensee 9.15.1 - Detects under what license a project is distributed"
+    );
+    let review = &prompter.steps[12].header;
+    assert!(review.contains("+        packages = [ pkgs.back pkgs.licensee pkgs.rust ];"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn pin_a_checkout_relative_to_the_flake() {
+    let dir = flake_dir(
+        "relative_checkout",
+        "{\n  inputs.local.url = \"path:./checkout\";\n  outputs = { self, local }: { };\n}\n",
+        None,
+    );
+    let checkout = dir.join("checkout");
+    fs::create_dir(&checkout).unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&checkout)
+            .args(&["-c", "user.name=test", "-c", "user.email=test@example.org"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+    git(&["tag", "v1.0"]);
+
+    // the working directory is the crate's, not the flake's
+    let flake = dir.join("flake.nix");
+    run(vec![
+        select(&["modify"]),
+        Answer::Type(flake.to_string_lossy().to_string()),
+        select(&["pin input"]),
+        select(&["local"]),
+        select(&["v1.0"]),
+        select(&["review changes"]),
+        select(&["write changes"]),
+        Answer::Type("exit".to_string()),
+    ]);
+
+    // nix only takes absolute paths in `git+file:` urls
+    let url = format!(
+        "inputs.local.url = \"git+file://{}?ref=refs/tags/v1.0&rev=",
+        fs::canonicalize(&checkout).unwrap().display()
+    );
+    assert!(fs::read_to_string(&flake).unwrap().contains(&url));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn unexpected_answers_are_errors() {
+    let (prompter, _) = run(vec![
+        Answer::Type("haskell".to_string()),
+        select(&["back"]),
+        Answer::Type("something else".to_string()),
+        select(&["exit"]),
+    ]);
+
+    assert_eq!(
+        prompter.steps[1].header,
+        "Encountered an error: there is no haskell flake generator yet"
+    );
+    assert_eq!(
+        prompter.steps[3].header,
+        "Encountered an error: something else can't be selected here"
+    );
+}
+
+#[test]
+pub fn typed_names_are_taken_as_they_are() {
+    let dir = flake_dir(
+        "typed_names",
+        include_str!("../../test_data/inputs.nix"),
+        None,
+    );
+    let flake = dir.join("flake.nix");
+    run(vec![
+        select(&["modify"]),
+        Answer::Type(flake.to_string_lossy().to_string()),
+        select(&["rename input"]),
+        select(&["hello"]),
+        Answer::Type("dotted".to_string()),
+        select(&["review changes"]),
+        select(&["write changes"]),
+        Answer::Type("exit".to_string()),
+    ]);
+
+    let written = fs::read_to_string(&flake).unwrap();
+    assert!(written.contains("inputs.dotted.url = \"abc\";"));
+    // the other inputs are laid out as they were
+    assert!(written.contains("  inputs = {\n    nixpkgs.url"));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -1,30 +1,324 @@
 pub mod rust;
+#[cfg(test)]
+pub(crate) mod script;
 
-use crate::parser::utils::{get_inputs, NixNode};
+#[cfg(test)]
+mod flow_tests;
+
+use crate::git::Revision;
+use crate::parser::diff::unified_diff;
+use crate::parser::flake::{Dep, DepList, Flake, Follows, InputStyle, InputsLayout};
+use crate::parser::lock::{lock_filename, FlakeLock};
+use crate::parser::packages::PackageIndex;
 
 use parse_display::{Display, FromStr};
 use skim::prelude::*;
 use smol_str::SmolStr;
-use std::{collections::HashMap, io::Cursor, str::FromStr};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{io::Cursor, str::FromStr, sync::Arc};
+
+/// what selecting an item would do, given the item's text, shown next to the items
+/// `None` if selecting it does nothing worth showing
+pub(crate) type Preview = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
 
 #[derive(Debug, Default, Clone)]
 pub(crate) struct UserMetadata {
-    pub(crate) root: Option<NixNode>,
-    pub(crate) inputs: Option<HashMap<String, NixNode>>,
+    pub(crate) flake: Option<Flake>,
+    /// previous versions of the flake and its lock file, most recent last
+    undo_history: Vec<(Flake, Option<FlakeLock>)>,
+    /// versions of the flake and its lock file that were undone, most recent last
+    redo_history: Vec<(Flake, Option<FlakeLock>)>,
     pub(crate) filename: Option<String>,
+    /// lock file next to the flake, if there is one
+    pub(crate) lock: Option<FlakeLock>,
+    /// lock file as it is on disk
+    pub(crate) original_lock: Option<FlakeLock>,
+    /// content of the flake as it is on disk
+    pub(crate) original: Option<String>,
+    /// name and url of the input being added, waiting on whether it's a flake
+    pub(crate) pending_input: Option<(String, String)>,
+    /// input chosen to be changed
+    pub(crate) selected_input: Option<String>,
+    /// follows chosen to be changed
+    pub(crate) selected_follows: Option<Follows>,
+    /// list of dependencies chosen to add a dependency to
+    pub(crate) selected_dep_list: Option<DepList>,
+    /// package index given on the command line, instead of the cached one
+    pub(crate) packages_file: Option<String>,
+    /// packages of nixpkgs to pick dependencies from, loaded when first needed
+    pub(crate) packages: Option<PackageIndex>,
     pub(crate) rust_options: rust_nix_templater::Options,
 }
 
 impl UserMetadata {
-    pub(crate) fn new_root(&mut self, root: NixNode) {
-        self.inputs = None;
-        self.root = Some(root);
+    pub(crate) fn with_packages_file(packages_file: Option<String>) -> Self {
+        Self {
+            packages_file,
+            ..Self::default()
+        }
+    }
+
+    /// forgets everything but the package index, which doesn't depend on the flake
+    pub(crate) fn start_over(&mut self) {
+        *self = Self {
+            packages_file: self.packages_file.take(),
+            packages: self.packages.take(),
+            ..Self::default()
+        };
+    }
+
+    /// replaces the flake by a freshly loaded one, forgetting the edit history
+    pub(crate) fn load_flake(&mut self, flake: Flake) {
+        self.flake = Some(flake);
+        self.undo_history.clear();
+        self.redo_history.clear();
+    }
+
+    /// replaces the flake by an edited version, which can be undone
+    pub(crate) fn new_flake(&mut self, flake: Flake) {
+        self.new_edit(flake, self.lock.clone());
+    }
+
+    /// replaces the flake and its lock file by edited versions, which can be undone together
+    pub(crate) fn new_edit(&mut self, flake: Flake, lock: Option<FlakeLock>) {
+        let previous_lock = std::mem::replace(&mut self.lock, lock);
+        if let Some(previous) = self.flake.replace(flake) {
+            if Some(&previous) != self.flake.as_ref() || previous_lock != self.lock {
+                self.undo_history.push((previous, previous_lock));
+                self.redo_history.clear();
+            }
+        }
+    }
+
+    fn can_undo(&self) -> bool {
+        !self.undo_history.is_empty()
+    }
+
+    fn can_redo(&self) -> bool {
+        !self.redo_history.is_empty()
+    }
+
+    /// goes back to the flake before the last edit
+    pub(crate) fn undo(&mut self) {
+        if let Some((previous, previous_lock)) = self.undo_history.pop() {
+            let current_lock = std::mem::replace(&mut self.lock, previous_lock);
+            if let Some(current) = self.flake.replace(previous) {
+                self.redo_history.push((current, current_lock));
+            }
+        }
+    }
+
+    /// reapplies the last undone edit
+    pub(crate) fn redo(&mut self) {
+        if let Some((next, next_lock)) = self.redo_history.pop() {
+            let current_lock = std::mem::replace(&mut self.lock, next_lock);
+            if let Some(current) = self.flake.replace(next) {
+                self.undo_history.push((current, current_lock));
+            }
+        }
+    }
+
+    /// undo and redo prompts, when there is something to undo or redo
+    fn history_prompts(&self) -> Vec<UserPrompt> {
+        let mut prompts = Vec::new();
+        if self.can_undo() {
+            prompts.push(UserPrompt::UndoEdit);
+        }
+        if self.can_redo() {
+            prompts.push(UserPrompt::RedoEdit);
+        }
+        prompts
+    }
+
+    pub(crate) fn has_pending_changes(&self) -> bool {
+        let flake_changed = match (&self.flake, &self.original) {
+            (Some(flake), Some(original)) => flake.to_string() != *original,
+            _ => false,
+        };
+        flake_changed || self.lock != self.original_lock
+    }
+
+    /// the flake as it will be written, the lines changed since it was read being formatted
+    pub(crate) fn flake_to_write(&self) -> Option<Flake> {
+        let flake = self.flake.as_ref()?;
+        Some(match &self.original {
+            Some(original) => flake.format_changes(original),
+            None => flake.clone(),
+        })
+    }
+
+    /// action showing the diff between the flake on disk and the flake as it will be written
+    pub(crate) fn review_changes(&self) -> UserAction {
+        let filename = self.filename.as_deref().unwrap_or("flake.nix");
+        let mut diff = match (self.flake_to_write(), &self.original) {
+            (Some(flake), Some(original)) => unified_diff(original, &flake.to_string(), filename),
+            _ => String::new(),
+        };
+        if let (Some(lock), Some(original_lock)) = (&self.lock, &self.original_lock) {
+            diff.push_str(&unified_diff(
+                &original_lock.to_json(),
+                &lock.to_json(),
+                &lock_filename(filename),
+            ));
+        }
+        UserAction::ReviewChanges(if diff.is_empty() {
+            "No changes.".to_string()
+        } else {
+            diff
+        })
+    }
+
+    /// loads the package index, if it isn't already
+    pub(crate) fn load_packages(&mut self) -> anyhow::Result<()> {
+        if self.packages.is_none() {
+            self.packages = PackageIndex::load(self.packages_file.as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// the inputs of the flake, along with what they're locked to if there is a lock file
+    /// every item starts with the input's name, see `input_name`
+    /// inputs only in the lock file are left out, as there is nothing to edit
+    fn input_items(&self) -> Vec<UserPrompt> {
+        match &self.lock {
+            Some(lock) => lock
+                .statuses(self.flake())
+                .iter()
+                .filter(|status| status.in_flake)
+                .map(|status| UserPrompt::Other(status.to_string().into()))
+                .collect(),
+            None => self
+                .flake()
+                .inputs()
+                .iter()
+                .map(|input| UserPrompt::Other(input.name().into()))
+                .collect(),
+        }
+    }
+
+    /// the directory of the flake, which relative paths in it start from
+    pub(crate) fn flake_dir(&self) -> PathBuf {
+        let filename = Path::new(self.filename.as_deref().unwrap_or_default());
+        let dir = match filename.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+    }
+
+    pub(crate) fn flake(&self) -> &Flake {
+        self.flake
+            .as_ref()
+            .expect("no flake loaded; this should be impossible, please file a bug report")
+    }
+
+    /// a preview of the diff `edit` makes to the flake, for the highlighted prompt
+    /// `edit` gives `None` for prompts that don't edit the flake
+    fn edit_preview<F>(&self, edit: F) -> Option<Preview>
+    where
+        F: Fn(&Flake, &UserPrompt) -> Option<anyhow::Result<Flake>> + Send + Sync + 'static,
+    {
+        let current = self.flake.as_ref()?.to_string();
+        let filename = self
+            .filename
+            .clone()
+            .unwrap_or_else(|| "flake.nix".to_string());
+        Some(Arc::new(move |item: &str| {
+            let prompt = UserPrompt::from_str(item).ok()?;
+            // syntax trees can't be sent to skim's threads, so the flake is parsed again
+            let flake = Flake::parse(current.clone()).ok()?;
+            Some(match edit(&flake, &prompt)? {
+                Ok(edited) => {
+                    let edited = edited.format_changes(&current).to_string();
+                    match unified_diff(&current, &edited, &filename) {
+                        diff if diff.is_empty() => "No changes.".to_string(),
+                        diff => diff,
+                    }
+                }
+                Err(err) => format!("Encountered an error: {}", err),
+            })
+        }))
     }
 
-    fn ensure_inputs(&mut self) -> &mut HashMap<String, NixNode> {
-        let root_ref = self.root.as_ref();
-        self.inputs
-            .get_or_insert_with(|| get_inputs(root_ref.unwrap()))
+    /// what selecting an item of `action` would do, if it can be shown before selecting it
+    pub(crate) fn get_preview(&self, action: &UserAction) -> Option<Preview> {
+        match action {
+            UserAction::Rust(act) => act.get_preview(self),
+            UserAction::IntroParsed => self.edit_preview(|flake, prompt| match prompt {
+                UserPrompt::FormatFlake => Some(Ok(flake.format())),
+                _ => None,
+            }),
+            UserAction::RemoveInput => self.edit_preview(|flake, prompt| match prompt {
+                UserPrompt::Other(item) => Some(flake.remove_input(input_name(item))),
+                _ => None,
+            }),
+            UserAction::UnusedInputs(_) => self.edit_preview(|flake, prompt| match prompt {
+                UserPrompt::Other(item) => Some(flake.remove_input(input_name(item))),
+                UserPrompt::RemoveUnused => Some(flake.remove_unused_inputs()),
+                _ => None,
+            }),
+            UserAction::SelectInputStyle => {
+                let input = self.selected_input.clone();
+                self.edit_preview(move |flake, prompt| match prompt {
+                    UserPrompt::InputStyle(style) => Some(match &input {
+                        Some(input) => flake.convert_input(input, *style),
+                        None => flake.convert_inputs(*style),
+                    }),
+                    _ => None,
+                })
+            }
+            UserAction::SelectInputsLayout => self.edit_preview(|flake, prompt| match prompt {
+                UserPrompt::InputsLayout(layout) => Some(flake.normalize_inputs(*layout)),
+                _ => None,
+            }),
+            UserAction::SelectRevision(revisions) => {
+                let input = self.selected_input.clone()?;
+                let revisions = revisions.clone();
+                let flake_dir = self.flake_dir();
+                self.edit_preview(move |flake, prompt| match prompt {
+                    UserPrompt::Other(item) => revisions
+                        .iter()
+                        .find(|revision| revision.label() == input_name(item))
+                        .map(|revision| flake.pin_input(&input, &revision.pin(), &flake_dir)),
+                    _ => None,
+                })
+            }
+            UserAction::ManageFollows => {
+                let lock = self.lock.clone();
+                self.edit_preview(move |flake, prompt| match prompt {
+                    UserPrompt::FollowNixpkgs => {
+                        Some(flake.follow_everywhere("nixpkgs", lock.as_ref()))
+                    }
+                    _ => None,
+                })
+            }
+            UserAction::ModifyFollows => {
+                let follows = self.selected_follows.clone()?;
+                self.edit_preview(move |flake, prompt| match prompt {
+                    UserPrompt::RemoveFollows => Some(flake.remove_follows(&follows)),
+                    _ => None,
+                })
+            }
+            UserAction::AddDep => {
+                let list = self.selected_dep_list.clone()?;
+                self.edit_preview(move |flake, prompt| match prompt {
+                    // items start with the package's attribute path
+                    UserPrompt::Other(item) => item
+                        .split_whitespace()
+                        .next()
+                        .map(|package| flake.add_dep(&list, package)),
+                    _ => None,
+                })
+            }
+            UserAction::RemoveDep => self.edit_preview(|flake, prompt| match prompt {
+                UserPrompt::Other(item) => {
+                    Dep::from_str(item).ok().map(|dep| flake.remove_dep(&dep))
+                }
+                _ => None,
+            }),
+            _ => None,
+        }
     }
 
     pub(crate) fn get_prompt_items(&mut self, action: &UserAction) -> Vec<UserPrompt> {
@@ -34,37 +328,165 @@ impl UserMetadata {
             UserAction::IntroParsed => vec![
                 UserPrompt::DeleteInput,
                 UserPrompt::AddInput,
+                UserPrompt::ChangeInput,
+                UserPrompt::RenameInput,
+                UserPrompt::PinInput,
+                UserPrompt::ConvertInput,
+                UserPrompt::NormalizeInputs,
+                UserPrompt::FormatFlake,
+                UserPrompt::UnusedInputs,
+                UserPrompt::ManageFollows,
+                UserPrompt::AddDep,
+                UserPrompt::RemoveDep,
+                UserPrompt::ReviewChanges,
+            ]
+            .into_iter()
+            .chain(self.history_prompts())
+            .chain(std::iter::once(UserPrompt::Back))
+            .collect(),
+            UserAction::ReviewChanges(_) => vec![
+                UserPrompt::WriteChanges,
+                UserPrompt::WriteChangesWithBackup,
+                UserPrompt::DiscardChanges,
+            ]
+            .into_iter()
+            .chain(self.history_prompts())
+            .chain(std::iter::once(UserPrompt::Back))
+            .collect(),
+            UserAction::CreateNew => vec![UserPrompt::SelectLang(Lang::Rust), UserPrompt::Back],
+            UserAction::ModifyExisting
+            | UserAction::AddInput
+            | UserAction::SetInputUrl
+            | UserAction::SetInputName
+            | UserAction::SetCheckout
+            | UserAction::NewFollows
+            | UserAction::SetFollowsTarget => vec![],
+            UserAction::AddDep => self
+                .packages
+                .iter()
+                .flat_map(PackageIndex::packages)
+                .map(|package| UserPrompt::Other(package.to_string().into()))
+                .collect(),
+            UserAction::IsInputFlake => vec![UserPrompt::Yes, UserPrompt::No, UserPrompt::Back],
+            UserAction::ManageFollows => self
+                .flake()
+                .follows()
+                .iter()
+                .map(|follows| UserPrompt::Other(follows.to_string().into()))
+                .chain(vec![
+                    UserPrompt::AddFollows,
+                    UserPrompt::FollowNixpkgs,
+                    UserPrompt::Back,
+                ])
+                .collect(),
+            UserAction::SelectDepList => self
+                .flake()
+                .dep_lists()
+                .iter()
+                .map(|list| UserPrompt::Other(list.to_string().into()))
+                .chain(std::iter::once(UserPrompt::Back))
+                .collect(),
+            UserAction::RemoveDep => self
+                .flake()
+                .deps()
+                .iter()
+                .map(|dep| UserPrompt::Other(dep.to_string().into()))
+                .chain(std::iter::once(UserPrompt::Back))
+                .collect(),
+            UserAction::SelectRevision(revisions) => revisions
+                .iter()
+                .map(|revision| UserPrompt::Other(revision.to_string().into()))
+                .chain(std::iter::once(UserPrompt::Back))
+                .collect(),
+            UserAction::UnusedInputs(names) => names
+                .iter()
+                .map(|name| UserPrompt::Other(name.into()))
+                .chain(vec![UserPrompt::RemoveUnused, UserPrompt::Back])
+                .collect(),
+            UserAction::ConvertInput => self
+                .input_items()
+                .into_iter()
+                .chain(vec![UserPrompt::AllInputs, UserPrompt::Back])
+                .collect(),
+            UserAction::SelectInputStyle => vec![
+                UserPrompt::InputStyle(InputStyle::Url),
+                UserPrompt::InputStyle(InputStyle::Attrs),
                 UserPrompt::Back,
             ],
-            UserAction::CreateNew => vec![UserPrompt::SelectLang(Lang::Rust), UserPrompt::Back],
-            UserAction::ModifyExisting => vec![],
-            UserAction::RemoveInput => {
-                // check cache
-                self.ensure_inputs()
-                    .keys()
-                    .map(|attr| UserPrompt::from_str(attr).unwrap())
-                    .chain(std::iter::once(UserPrompt::Back))
-                    .collect()
-            }
+            UserAction::SelectInputsLayout => vec![
+                UserPrompt::InputsLayout(InputsLayout::Nested),
+                UserPrompt::InputsLayout(InputsLayout::Sets),
+                UserPrompt::InputsLayout(InputsLayout::Dotted),
+                UserPrompt::Back,
+            ],
+            UserAction::ModifyFollows => vec![
+                UserPrompt::RetargetFollows,
+                UserPrompt::RemoveFollows,
+                UserPrompt::Back,
+            ],
+            UserAction::RemoveInput
+            | UserAction::ChangeInput
+            | UserAction::RenameInput
+            | UserAction::PinInput
+            | UserAction::AddFollows => self
+                .input_items()
+                .into_iter()
+                .chain(std::iter::once(UserPrompt::Back))
+                .collect(),
             UserAction::Error(_) => vec![UserPrompt::Back, UserPrompt::StartOver, UserPrompt::Exit],
-            x => unimplemented!("prompt not implemented for: {:?}", x),
         }
     }
 
-    pub(crate) fn get_user_prompt(&mut self, a: &UserAction) -> anyhow::Result<UserPrompt> {
-        let input = query_user_input(
-            a.to_string().lines(),
-            self.get_prompt_items(a).into_iter(),
+    /// the prompts the user selected, several of them if the action allows it
+    pub(crate) fn get_user_prompts(
+        &mut self,
+        prompter: &mut dyn Prompter,
+        a: &UserAction,
+    ) -> anyhow::Result<Vec<UserPrompt>> {
+        let items = self.get_prompt_items(a);
+        let inputs = prompter.query(
+            &a.to_string(),
+            items.clone(),
             matches!(
                 a,
                 UserAction::ModifyExisting | UserAction::Rust(rust::Action::SetIcon)
             ),
+            a.is_multi_select(),
+            self.get_preview(a),
         )?;
-        Ok(UserPrompt::from_str(&input)
-            .expect("could not make prompt; this should be impossible, please file a bug report"))
+        // items are taken as they were offered, only what was typed is parsed,
+        // e.g. a package described as "... license" is not a license
+        Ok(inputs
+            .iter()
+            .map(|input| {
+                items
+                    .iter()
+                    .find(|item| item.to_string() == *input)
+                    .cloned()
+                    .unwrap_or_else(|| typed_prompt(a, input))
+            })
+            .collect())
+    }
+}
+
+/// what typing `input` means for `action`
+/// text typed where a name, url, or path is asked for is taken as it is,
+/// e.g. an input renamed to `dotted` doesn't normalize the inputs
+fn typed_prompt(action: &UserAction, input: &str) -> UserPrompt {
+    let prompt = UserPrompt::from_str(input)
+        .expect("could not make prompt; this should be impossible, please file a bug report");
+    match prompt {
+        UserPrompt::Back | UserPrompt::Exit => prompt,
+        _ if action.takes_text() => UserPrompt::Other(input.into()),
+        _ => prompt,
     }
 }
 
+/// the name of the input an item listing inputs is about
+pub(crate) fn input_name(item: &str) -> &str {
+    item.split_whitespace().next().unwrap_or_default()
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Display, FromStr)]
 pub(crate) enum UserPrompt {
     #[display("start over")]
@@ -81,11 +503,63 @@ pub(crate) enum UserPrompt {
     DeleteInput,
     #[display("add input")]
     AddInput,
+    #[display("change input")]
+    ChangeInput,
+    #[display("rename input")]
+    RenameInput,
+    #[display("pin input")]
+    PinInput,
+    #[display("convert input between url and attribute set")]
+    ConvertInput,
+    #[display("all inputs")]
+    AllInputs,
+    #[display("normalize inputs")]
+    NormalizeInputs,
+    #[display("format the whole flake")]
+    FormatFlake,
+    #[display("unused inputs")]
+    UnusedInputs,
+    #[display("remove all unused inputs")]
+    RemoveUnused,
+    #[display("manage follows")]
+    ManageFollows,
+    #[display("add follows")]
+    AddFollows,
+    #[display("make all inputs follow nixpkgs")]
+    FollowNixpkgs,
+    #[display("change followed input")]
+    RetargetFollows,
+    #[display("remove follows")]
+    RemoveFollows,
+    #[display("add dependency")]
+    AddDep,
+    #[display("remove dependency")]
+    RemoveDep,
+    #[display("review changes")]
+    ReviewChanges,
+    #[display("write changes")]
+    WriteChanges,
+    #[display("write changes, keeping a backup")]
+    WriteChangesWithBackup,
+    #[display("discard changes")]
+    DiscardChanges,
+    #[display("undo last edit")]
+    UndoEdit,
+    #[display("redo")]
+    RedoEdit,
+    #[display("yes")]
+    Yes,
+    #[display("no")]
+    No,
     #[display("{0}")]
     Rust(rust::Prompt),
     #[display("{0}")]
     SelectLang(Lang),
     #[display("{0}")]
+    InputStyle(InputStyle),
+    #[display("{0}")]
+    InputsLayout(InputsLayout),
+    #[display("{0}")]
     Other(SmolStr),
 }
 
@@ -99,24 +573,92 @@ pub(crate) enum UserAction {
     ModifyExisting,
     #[display("Choose a flake generator.")]
     CreateNew,
-    #[display("Add a dependency to your flake.\nPlease select an package from nixpkgs.")]
+    #[display(
+        "Add a dependency to your flake.\nPlease select the derivation and the list to add it to."
+    )]
+    SelectDepList,
+    #[display("Add a dependency to your flake.\nPlease select a package from nixpkgs, or type its name. Example: openssl")]
     AddDep,
-    #[display("Remove a dependency from your flake.\nPlease select a input to remove.")]
+    #[display("Remove a dependency from your flake.\nPlease select a dependency to remove.")]
     RemoveDep,
     #[display(
-        "Add an input to your flake.\nPlease input a flake url and indicate if it's a flake"
+        "Add an input to your flake.\nType the input name and url seperated by a space. Example: nixpkgs github:NixOS/nixpkgs"
     )]
     AddInput,
     #[display("Please select an input to remove.")]
     RemoveInput,
+    #[display("Please select an input to change.")]
+    ChangeInput,
+    #[display("Type the new url of the input.")]
+    SetInputUrl,
+    #[display("Please select an input to rename.")]
+    RenameInput,
+    #[display("Type the new name of the input.")]
+    SetInputName,
+    #[display("Please select an input to pin.")]
+    PinInput,
+    #[display("Please select an input to convert.")]
+    ConvertInput,
+    #[display("Write the input as a url string, or as an attribute set?\nExample: url = \"github:NixOS/nixpkgs\"; or type = \"github\"; owner = \"NixOS\"; repo = \"nixpkgs\";")]
+    SelectInputStyle,
+    #[display("How should the inputs be laid out? They will be sorted by name.\nnested: inputs = {{ nixpkgs.url = \"...\"; }};\nsets: inputs = {{ nixpkgs = {{ url = \"...\"; }}; }};\ndotted: inputs.nixpkgs.url = \"...\";")]
+    SelectInputsLayout,
+    #[display("Type the path of a local git checkout of the input.")]
+    SetCheckout,
+    #[display("Please select the revision to pin the input to.")]
+    SelectRevision(Vec<Revision>),
+    #[display("These inputs are never used by the outputs.\nSelect one to remove it.")]
+    UnusedInputs(Vec<String>),
+    #[display("Please select a follows to modify.")]
+    ManageFollows,
+    #[display("What would you like to do with this follows?")]
+    ModifyFollows,
+    #[display("Type the input that should be followed.")]
+    SetFollowsTarget,
+    #[display("Please select the input whose input should follow another input.")]
+    AddFollows,
+    #[display("Type the name of the input's input and the input it should follow seperated by a space. Example: nixpkgs nixpkgs")]
+    NewFollows,
     #[display("Is the input a flake?")]
     IsInputFlake,
+    #[display("Unsaved changes to the flake:\n{0}")]
+    ReviewChanges(String),
     #[display("Encountered an error: {0}")]
     Error(anyhow::Error),
     #[display("{0}")]
     Rust(rust::Action),
 }
 
+impl UserAction {
+    /// whether several items can be selected at once, to be applied as a single edit
+    pub(crate) fn is_multi_select(&self) -> bool {
+        matches!(
+            self,
+            UserAction::RemoveInput
+                | UserAction::UnusedInputs(_)
+                | UserAction::AddDep
+                | UserAction::RemoveDep
+                | UserAction::Rust(rust::Action::Intro)
+        )
+    }
+
+    /// whether something is typed, rather than selected from the items
+    pub(crate) fn takes_text(&self) -> bool {
+        match self {
+            UserAction::ModifyExisting
+            | UserAction::AddInput
+            | UserAction::SetInputUrl
+            | UserAction::SetInputName
+            | UserAction::SetCheckout
+            | UserAction::NewFollows
+            | UserAction::SetFollowsTarget
+            | UserAction::AddDep => true,
+            UserAction::Rust(act) => act.takes_text(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Eq, PartialEq, Debug, Copy, Clone, Display, FromStr)]
 pub(crate) enum Lang {
     #[display("rust")]
@@ -129,11 +671,62 @@ pub(crate) enum Lang {
     JavaScript,
 }
 
+/// How the user is asked to choose between items, or to type something
+/// so that the interface can be driven without a terminal
+pub(crate) trait Prompter {
+    /// the items selected, or what was typed if nothing was, see `query_user_input`
+    fn query(
+        &mut self,
+        header: &str,
+        items: Vec<UserPrompt>,
+        files: bool,
+        multi: bool,
+        preview: Option<Preview>,
+    ) -> anyhow::Result<Vec<String>>;
+}
+
+/// Asks the user with skim
+pub(crate) struct SkimPrompter;
+
+impl Prompter for SkimPrompter {
+    fn query(
+        &mut self,
+        header: &str,
+        items: Vec<UserPrompt>,
+        files: bool,
+        multi: bool,
+        preview: Option<Preview>,
+    ) -> anyhow::Result<Vec<String>> {
+        query_user_input(header.lines(), items.into_iter(), files, multi, preview)
+    }
+}
+
+/// an item along with the preview of what selecting it would do
+struct PreviewItem {
+    text: String,
+    preview: Preview,
+}
+
+impl SkimItem for PreviewItem {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.text)
+    }
+
+    fn preview(&self, _context: PreviewContext) -> ItemPreview {
+        ItemPreview::Text((self.preview)(&self.text).unwrap_or_default())
+    }
+}
+
+/// asks the user to select items, or to type something, returning what was typed if nothing was
+/// selected; with `multi`, several items can be selected with tab
+/// with a `preview`, what selecting the highlighted item would do is shown next to the items
 pub(crate) fn query_user_input<'a, PI, II>(
     prompt: PI,
     items: II,
     files: bool,
-) -> anyhow::Result<String>
+    multi: bool,
+    preview: Option<Preview>,
+) -> anyhow::Result<Vec<String>>
 where
     PI: Iterator<Item = &'a str> + DoubleEndedIterator,
     II: Iterator<Item = UserPrompt> + DoubleEndedIterator,
@@ -156,19 +749,48 @@ where
     let options = SkimOptionsBuilder::default()
         .header(Some(&agg_prompt))
         .header_lines(header_len)
-        .prompt(Some("Provide input: "))
+        .prompt(Some(if multi {
+            "Provide input (tab selects several): "
+        } else {
+            "Provide input: "
+        }))
         .inline_info(false)
-        .multi(false)
+        .multi(multi)
+        // the items preview themselves, no command is run
+        .preview(if preview.is_some() { Some("") } else { None })
+        .preview_window(Some("right:60%:wrap"))
         .build()
         .expect("failed to build skim options: something is very wrong");
 
-    let item_reader = SkimItemReader::default();
-    let items =
-        (!files).then(|| item_reader.of_bufread(Cursor::new(agg(items.map(|i| i.to_string())).1)));
+    let items = match preview {
+        _ if files => None,
+        Some(preview) => {
+            let (sender, receiver): (SkimItemSender, SkimItemReceiver) = unbounded();
+            // in the same order as the items read below
+            for item in items.rev() {
+                let item = PreviewItem {
+                    text: item.to_string(),
+                    preview: preview.clone(),
+                };
+                // skim only stops reading items when it's done, which can't be before this returns
+                let _ = sender.send(Arc::new(item));
+            }
+            Some(receiver)
+        }
+        None => Some(
+            SkimItemReader::default().of_bufread(Cursor::new(agg(items.map(|i| i.to_string())).1)),
+        ),
+    };
 
     let result = Skim::run_with(&options, items).expect("skim failed: something is very wrong");
-    Ok(result
+    let selected = result
         .selected_items
-        .get(0)
-        .map_or(result.query, |item| item.output().to_string()))
+        .iter()
+        .map(|item| item.output().to_string())
+        .collect::<Vec<_>>();
+    Ok(if selected.is_empty() {
+        vec![result.query]
+    } else {
+        selected
+    })
 }
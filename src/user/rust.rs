@@ -3,9 +3,20 @@ use parse_display::{Display, FromStr};
 use rust_nix_templater::{options::RustToolchainChannel, *};
 use smol_str::SmolStr;
 
+use crate::parser::file::format_flake_file;
 use crate::ActionStack;
 
-use super::{UserAction, UserMetadata, UserPrompt};
+use super::{Preview, UserAction, UserMetadata, UserPrompt};
+use std::{str::FromStr, sync::Arc};
+
+/// path of the flake rust-nix-templater generates with `options`
+pub(crate) fn generated_flake(options: &Options) -> String {
+    options
+        .out_dir
+        .join("flake.nix")
+        .to_string_lossy()
+        .to_string()
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Display, FromStr)]
 pub(crate) enum Prompt {
@@ -62,6 +73,18 @@ impl From<Prompt> for UserPrompt {
 }
 
 impl Prompt {
+    /// whether the prompt flips an option, rather than asking for something
+    pub(crate) fn is_toggle(&self) -> bool {
+        matches!(
+            self,
+            Prompt::ToggleBuildOutputs(_)
+                | Prompt::ToggleAppOutputs(_)
+                | Prompt::ToggleLibrary(_)
+                | Prompt::ToggleGithubCi(_)
+                | Prompt::ToggleGitlabCi(_)
+        )
+    }
+
     pub(crate) fn process_prompt(
         &self,
         action_stack: &mut ActionStack,
@@ -69,7 +92,10 @@ impl Prompt {
     ) {
         let act = match self {
             Prompt::Generate => match run_with_options(user_data.rust_options.clone(), false) {
-                Ok(_) => Action::Generated.into(),
+                Ok(_) => match format_flake_file(&generated_flake(&user_data.rust_options)) {
+                    Ok(()) => Action::Generated.into(),
+                    Err(err) => UserAction::Error(err),
+                },
                 Err(err) => {
                     UserAction::Error(anyhow!(format!("rust-nix-templater failed: {}", err)))
                 }
@@ -166,6 +192,14 @@ impl From<Action> for UserAction {
 }
 
 impl Action {
+    /// whether something is typed, rather than selected from the items
+    pub(crate) fn takes_text(&self) -> bool {
+        !matches!(
+            self,
+            Action::Intro | Action::Generated | Action::SetToolchain
+        )
+    }
+
     pub(crate) fn get_prompt_items(&self, user_data: &mut UserMetadata) -> Vec<UserPrompt> {
         let map_or_def =
             |opt: Option<&String>| opt.map_or_else(|| SmolStr::new_inline("not set"), Into::into);
@@ -264,6 +298,31 @@ impl Action {
         }
     }
 
+    /// the options the flake will be generated with once the highlighted choice is made
+    pub(crate) fn get_preview(&self, user_data: &UserMetadata) -> Option<Preview> {
+        if !matches!(
+            self,
+            Action::Intro | Action::SetToolchain | Action::SetLicense
+        ) {
+            return None;
+        }
+        let options = user_data.rust_options.clone();
+        Some(Arc::new(move |item: &str| {
+            let mut options = options.clone();
+            match Prompt::from_str(item).ok()? {
+                Prompt::ChooseToolchain(toolchain) => options.rust_toolchain_channel = toolchain,
+                Prompt::ChooseLicense(license) => options.package_license = Some(license.into()),
+                Prompt::ToggleBuildOutputs(_) => options.disable_build = !options.disable_build,
+                Prompt::ToggleAppOutputs(_) => options.disable_app = !options.disable_app,
+                Prompt::ToggleLibrary(_) => options.package_lib = !options.package_lib,
+                Prompt::ToggleGithubCi(_) => options.github_ci = !options.github_ci,
+                Prompt::ToggleGitlabCi(_) => options.gitlab_ci = !options.gitlab_ci,
+                _ => return None,
+            }
+            Some(options_summary(&options))
+        }))
+    }
+
     // Called when `UserPrompt::Other(String)`
     pub(crate) fn process_action(
         &self,
@@ -314,3 +373,28 @@ impl Action {
         action_stack.pop();
     }
 }
+
+/// the options that change what the generated flake looks like, one per line
+fn options_summary(options: &Options) -> String {
+    let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+    let or_not_set =
+        |value: &Option<String>| value.clone().unwrap_or_else(|| "not set".to_string());
+    [
+        format!("package name: {}", or_not_set(&options.package_name)),
+        format!("toolchain: {}", options.rust_toolchain_channel),
+        format!("license: {}", or_not_set(&options.package_license)),
+        format!(
+            "systems: {}",
+            options
+                .package_systems
+                .as_ref()
+                .map_or_else(|| "not set".to_string(), |systems| systems.join(" "))
+        ),
+        format!("build outputs: {}", yes_no(!options.disable_build)),
+        format!("app outputs: {}", yes_no(!options.disable_app)),
+        format!("library: {}", yes_no(options.package_lib)),
+        format!("github actions: {}", yes_no(options.github_ci)),
+        format!("gitlab CI: {}", yes_no(options.gitlab_ci)),
+    ]
+    .join("\n")
+}
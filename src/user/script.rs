@@ -0,0 +1,83 @@
+use super::{Preview, Prompter, UserPrompt};
+
+use std::collections::VecDeque;
+
+/// What the user does when asked something
+#[derive(Debug, Clone)]
+pub(crate) enum Answer {
+    /// selects the items starting with each of these,
+    /// e.g. `nixpkgs` for the item `nixpkgs github:NixOS/nixpkgs`
+    Select(Vec<&'static str>),
+    /// types something without selecting any item
+    Type(String),
+}
+
+/// A prompt the script answered
+#[derive(Debug, Clone)]
+pub(crate) struct Step {
+    pub(crate) header: String,
+    pub(crate) items: Vec<String>,
+    /// the preview of the first item selected, if there is one
+    pub(crate) preview: Option<String>,
+}
+
+/// Answers prompts with a recorded sequence of answers, keeping what it was asked
+/// panics when the answers don't fit what is asked, or when there are none left
+pub(crate) struct ScriptedPrompter {
+    answers: VecDeque<Answer>,
+    pub(crate) steps: Vec<Step>,
+}
+
+impl ScriptedPrompter {
+    pub(crate) fn new(answers: Vec<Answer>) -> Self {
+        Self {
+            answers: answers.into(),
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl Prompter for ScriptedPrompter {
+    fn query(
+        &mut self,
+        header: &str,
+        items: Vec<UserPrompt>,
+        _files: bool,
+        multi: bool,
+        preview: Option<Preview>,
+    ) -> anyhow::Result<Vec<String>> {
+        let items = items.iter().map(UserPrompt::to_string).collect::<Vec<_>>();
+        let answer = self
+            .answers
+            .pop_front()
+            .unwrap_or_else(|| panic!("the script ended, but was asked: {}", header));
+        let selected = match answer {
+            Answer::Type(text) => vec![text],
+            Answer::Select(prefixes) => {
+                assert!(
+                    multi || prefixes.len() == 1,
+                    "several items selected, but only one can be: {}",
+                    header
+                );
+                prefixes
+                    .iter()
+                    .map(|prefix| {
+                        items
+                            .iter()
+                            .find(|item| item.starts_with(prefix))
+                            .cloned()
+                            .unwrap_or_else(|| {
+                                panic!("no item {} in {:?} for: {}", prefix, items, header)
+                            })
+                    })
+                    .collect()
+            }
+        };
+        self.steps.push(Step {
+            header: header.to_string(),
+            preview: preview.and_then(|preview| preview(&selected[0])),
+            items,
+        });
+        Ok(selected)
+    }
+}
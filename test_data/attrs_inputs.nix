@@ -0,0 +1,21 @@
+{
+  inputs = {
+    nixpkgs = {
+      type = "github";
+      owner = "NixOS";
+      repo = "nixpkgs";
+      ref = "nixos-21.05";
+    };
+    nixCargoIntegration = {
+      # pinned until the next release
+      url = "github:yusdacra/nix-cargo-integration?dir=lib";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+    private.type = "git";
+    private.url = "ssh://git@example.org/private.git";
+    private.submodules = true;
+    private.flake = false;
+  };
+
+  outputs = { self, nixpkgs, nixCargoIntegration, private }: {};
+}
@@ -0,0 +1,19 @@
+{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+  outputs = { self, nixpkgs }:
+    let
+      pkgs = nixpkgs.legacyPackages.x86_64-linux;
+    in
+    {
+      packages.x86_64-linux.hello = pkgs.stdenv.mkDerivation {
+        name = "hello";
+        buildInputs = [
+          pkgs.openssl # tls
+          pkgs.zlib
+        ];
+      };
+      devShell.x86_64-linux = pkgs.mkShell {
+        nativeBuildInputs = with pkgs; [ cargo rustc ];
+      };
+    };
+}
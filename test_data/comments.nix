@@ -0,0 +1,20 @@
+# a flake with comments everywhere
+{
+  description = "comments"; # what it is
+
+  inputs = {
+    # the package set
+    nixpkgs.url = "github:NixOS/nixpkgs/nixpkgs-unstable"; # unstable
+
+    # utilities
+    # used by the outputs
+    utils.url = "github:numtide/flake-utils";
+    nixCargoIntegration = {
+      url = "github:yusdacra/nix-cargo-integration";
+      # share nixpkgs
+      inputs.nixpkgs.follows = "nixpkgs"; # saves a download
+    };
+  };
+
+  outputs = { self, nixpkgs, utils, nixCargoIntegration }: {};
+}
@@ -0,0 +1,16 @@
+{
+  inputs = {
+    nixpkgs = {
+      type = "github";
+      owner = "NixOS";
+      repo = "nixpkgs";
+      ref = "nixos-21.05";
+    };
+    private.type = "git";
+    private.url = "ssh://git@example.org/private.git";
+    private.submodules = true;
+    private.flake = false;
+  };
+
+  outputs = { self, nixpkgs, private }: {};
+}
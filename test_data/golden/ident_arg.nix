@@ -0,0 +1,10 @@
+{
+  inputs.hello.url = "abc";
+  inputs.other.url = "hello_world";
+
+  outputs = inputs: {
+    a = inputs.hello;
+    b = inputs ? other;
+    c = { inputs, ... }: inputs.hello;
+  };
+}
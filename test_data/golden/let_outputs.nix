@@ -0,0 +1,13 @@
+let
+  outputs = { self, hello, ... }@inputs: {
+    a = hello;
+    b = inputs.another_one.packages;
+    c = let hello = 1; in hello;
+  };
+in
+{
+  inputs.hello.url = "abc";
+  inputs.another_one.url = "hello_world";
+
+  inherit outputs;
+}
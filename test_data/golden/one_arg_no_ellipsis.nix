@@ -0,0 +1,7 @@
+{
+  inputs.another_one = {
+    url = "hello_world";
+  };
+
+  outputs = inputs@{}: {};
+}
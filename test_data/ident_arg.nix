@@ -0,0 +1,10 @@
+{
+  inputs.hello.url = "abc";
+  inputs.another_one.url = "hello_world";
+
+  outputs = inputs: {
+    a = inputs.hello;
+    b = inputs ? another_one;
+    c = { inputs, ... }: inputs.hello;
+  };
+}